@@ -0,0 +1,9 @@
+#![no_main]
+
+use gbrs::core::mbc::CartridgeHeader;
+use libfuzzer_sys::fuzz_target;
+
+// any ROM, however broken, should give an error rather than a panic
+fuzz_target!(|data: &[u8]| {
+    let _ = CartridgeHeader::new(data);
+});
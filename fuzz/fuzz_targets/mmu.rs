@@ -0,0 +1,25 @@
+#![no_main]
+
+use gbrs::core::mmu::Mmu;
+use libfuzzer_sys::fuzz_target;
+
+// the first 2 bytes are how many 4 byte accesses follow, the rest is the ROM. Each access is
+// a kind (even reads, odd writes), a little endian address and a value
+fuzz_target!(|data: &[u8]| {
+    let Some((count, rest)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+    let len = (u16::from_le_bytes(*count) as usize * 4).min(rest.len());
+    let (accesses, rom) = rest.split_at(len);
+    let Ok(mut mmu) = Mmu::new(rom.to_vec(), 48000) else {
+        return;
+    };
+    for access in accesses.chunks_exact(4) {
+        let addr = u16::from_le_bytes([access[1], access[2]]);
+        if access[0] % 2 == 0 {
+            let _ = mmu.read(addr);
+        } else {
+            let _ = mmu.write(addr, access[3]);
+        }
+    }
+});
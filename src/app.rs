@@ -1,69 +1,558 @@
+use std::path::PathBuf;
+
 use cpal::traits::{DeviceTrait, HostTrait};
 use poll_promise::Promise;
 
-use crate::{core::cpu::Cpu, screen::Screen};
+use crate::{
+    archive::{self, RomEntry},
+    core::{
+        cpu::Cpu,
+        ir::IrLink,
+        mbc::{Mapper, camera, info::RomInfo},
+        mmu::RamInit,
+    },
+    patch::{self, PatchFormat},
+    screen::Screen,
+};
+
+/// A file's contents and, on native builds, where it came from so the save can go next to it
+pub type PickedFile = (Vec<u8>, Option<PathBuf>);
 
 pub struct GbApp {
-    promise: Option<Promise<Option<Vec<u8>>>>,
+    promise: Option<Promise<Vec<PickedFile>>>,
+    patch_promise: Option<Promise<Option<Vec<u8>>>>,
+    save_promise: Option<Promise<Option<Vec<u8>>>>,
+    camera_promise: Option<Promise<Vec<PickedFile>>>,
+    export_promise: Option<Promise<()>>,
     screen: Option<Screen>,
+    error: Option<String>,
+    show_rom_info: bool,
+    /// Header report for the last ROM loaded, kept even when it couldn't be started
+    rom_info: Option<RomInfo>,
+    /// ROMs from an archive with more than one in it, waiting for the user to pick, and where the archive is
+    rom_choices: Option<(Vec<RomEntry>, Option<PathBuf>)>,
+    /// The unpatched ROM and the patches applied to it, so more can be added later
+    base_rom: Option<Vec<u8>>,
+    patches: Vec<Vec<u8>>,
+    /// What RAM holds when a ROM is started
+    pub ram_init: RamInit,
+    /// Hex seed typed in the Power-on RAM menu, a new one is picked each restart while it's empty
+    ram_seed: String,
+    /// The `.sav` next to the running ROM, battery saves are loaded from and written back to it
+    save_path: Option<PathBuf>,
+    /// Save data as it was last loaded or written, None until the game at `save_path` is running
+    saved: Option<Vec<u8>>,
+    /// When the save was last checked for changes, in egui's seconds
+    last_store: f64,
+    /// Point IR carts' LED back at their own receiver
+    ir_loopback: bool,
 }
 
 impl GbApp {
     pub fn new(_: &eframe::CreationContext<'_>) -> Self {
         GbApp {
             promise: None,
+            patch_promise: None,
+            save_promise: None,
+            camera_promise: None,
+            export_promise: None,
             screen: None,
+            error: None,
+            show_rom_info: false,
+            rom_info: None,
+            rom_choices: None,
+            base_rom: None,
+            patches: Vec::new(),
+            ram_init: RamInit::default(),
+            ram_seed: String::new(),
+            save_path: None,
+            saved: None,
+            last_store: 0.0,
+            ir_loopback: false,
+        }
+    }
+
+    /// Opens a ROM, or an archive of them, asking which one to run if there's a choice. Any
+    /// patches picked alongside it are applied on load
+    fn open(&mut self, files: Vec<PickedFile>, ctx: &egui::Context) {
+        let (patches, files): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|(file, _)| PatchFormat::detect(file).is_some());
+        let Ok([(file, path)]) = <[PickedFile; 1]>::try_from(files) else {
+            self.error = Some("Choose one ROM, along with any patches for it".to_string());
+            return;
+        };
+        self.patches = patches.into_iter().map(|(patch, _)| patch).collect();
+        match archive::roms(file) {
+            Ok(mut roms) if roms.len() == 1 => {
+                self.set_save_path(path.map(|path| path.with_extension("sav")));
+                self.load_rom(roms.remove(0).rom, ctx);
+            }
+            Ok(roms) => self.rom_choices = Some((roms, path)),
+            Err(e) => {
+                log::error!("GbApp: failed to open file: {e:#}");
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+    }
+
+    fn load_rom(&mut self, rom: Vec<u8>, ctx: &egui::Context) {
+        // a restart reads the save back, so the running game's has to be on disk first
+        self.store_battery();
+        self.base_rom = Some(rom.clone());
+        let rom = match self
+            .patches
+            .iter()
+            .try_fold(rom, |rom, p| patch::apply(&rom, p))
+        {
+            Ok(rom) => rom,
+            Err(e) => {
+                log::error!("GbApp: failed to patch ROM: {e:#}");
+                self.error = Some(format!("{e:#}"));
+                self.rom_info = None;
+                return;
+            }
+        };
+        self.rom_info = (rom.len() >= 0x150).then(|| RomInfo::new(&rom));
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .expect("failed to find a default output device");
+        let config = device.default_output_config().unwrap();
+        let sample_rate = config.sample_rate().0;
+        match Cpu::new(rom, sample_rate) {
+            Ok(mut cpu) => {
+                cpu.mmu.init_ram(self.ram_init);
+                self.load_battery(&mut cpu);
+                if self.ir_loopback {
+                    cpu.mmu.cartridge.mbc.connect_ir(IrLink::loopback());
+                }
+                self.screen = Some(Screen::new(cpu, ctx));
+                self.error = None;
+            }
+            Err(e) => {
+                log::error!("GbApp: failed to load ROM: {e:#}");
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+    }
+
+    /// Starts with a ROM or archive already opened, plus any patches for it, e.g. ones passed
+    /// on the command line
+    pub fn with_rom(cc: &eframe::CreationContext<'_>, files: Vec<PickedFile>) -> Self {
+        let mut app = Self::new(cc);
+        app.promise = Some(Promise::from_ready(files));
+        app
+    }
+
+    /// Switches to another game's save, writing out the one that's running first
+    fn set_save_path(&mut self, path: Option<PathBuf>) {
+        self.store_battery();
+        self.save_path = path;
+        self.saved = None;
+    }
+
+    /// Loads the `.sav` next to the ROM into a battery-backed cart, a missing one is a new game
+    fn load_battery(&mut self, cpu: &mut Cpu) {
+        let mbc = &mut cpu.mmu.cartridge.mbc;
+        if let (Some(path), Some(_)) = (&self.save_path, mbc.save()) {
+            match std::fs::read(path) {
+                Ok(save) => match mbc.load_save(&save) {
+                    Ok(()) => log::info!("GbApp: loaded save from {}", path.display()),
+                    Err(e) => log::error!("GbApp: failed to load save: {e}"),
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => log::error!("GbApp: failed to read {}: {e}", path.display()),
+            }
         }
+        self.saved = mbc.save();
+    }
+
+    /// Writes the battery save next to the ROM if it changed since it was loaded or last written
+    fn store_battery(&mut self) {
+        let (Some(path), Some(screen), Some(saved)) = (&self.save_path, &self.screen, &self.saved)
+        else {
+            return;
+        };
+        let Some(save) = screen.cpu.mmu.cartridge.mbc.save() else {
+            return;
+        };
+        if save == *saved {
+            return;
+        }
+        match std::fs::write(path, &save) {
+            Ok(()) => {
+                log::info!("GbApp: wrote save to {}", path.display());
+                self.saved = Some(save);
+            }
+            Err(e) => log::error!("GbApp: failed to write {}: {e}", path.display()),
+        }
+    }
+}
+
+fn open_file() -> Promise<Option<Vec<u8>>> {
+    Promise::spawn_local(async {
+        if let Some(file) = rfd::AsyncFileDialog::new().pick_file().await {
+            let f = file.read().await;
+            Some(f)
+        } else {
+            None
+        }
+    })
+}
+
+fn open_files() -> Promise<Vec<PickedFile>> {
+    Promise::spawn_local(async {
+        let mut files = Vec::new();
+        if let Some(handles) = rfd::AsyncFileDialog::new().pick_files().await {
+            for file in handles {
+                #[cfg(not(target_arch = "wasm32"))]
+                let path = Some(file.path().to_path_buf());
+                #[cfg(target_arch = "wasm32")]
+                let path = None;
+                files.push((file.read().await, path));
+            }
+        }
+        files
+    })
+}
+
+fn export_file(name: String, data: Vec<u8>) -> Promise<()> {
+    Promise::spawn_local(async move {
+        if let Some(file) = rfd::AsyncFileDialog::new()
+            .set_file_name(name)
+            .save_file()
+            .await
+        {
+            if let Err(e) = file.write(&data).await {
+                log::error!("GbApp: failed to write file: {e}");
+            }
+        }
+    })
+}
+
+fn rom_info_ui(ui: &mut egui::Ui, info: &RomInfo) {
+    egui::Grid::new("rom_info").striped(true).show(ui, |ui| {
+        let mut row = |name: &str, value: String| {
+            ui.label(name);
+            ui.monospace(value);
+            ui.end_row();
+        };
+        row("Title", info.title.clone());
+        row(
+            "Manufacturer",
+            info.manufacturer.clone().unwrap_or_default(),
+        );
+        row("CGB", format!("{:?}", info.cgb));
+        row("SGB", info.sgb.to_string());
+        row("Cartridge type", format!("{:02x}", info.cartridge_type));
+        row(
+            "Licensee",
+            match info.licensee_name {
+                Some(name) => format!("{} ({name})", info.licensee),
+                None => info.licensee.clone(),
+            },
+        );
+        row("Destination", format!("{:?}", info.destination));
+        row("Version", info.version.to_string());
+        row(
+            "Logo",
+            if info.logo_valid { "ok" } else { "bad" }.to_string(),
+        );
+        row(
+            "Header checksum",
+            format!(
+                "{:02x} (computed {:02x})",
+                info.header_checksum, info.computed_header_checksum
+            ),
+        );
+        row(
+            "Global checksum",
+            format!(
+                "{:04x} (computed {:04x})",
+                info.global_checksum, info.computed_global_checksum
+            ),
+        );
+        row(
+            "ROM size",
+            format!(
+                "{} (file {})",
+                info.header_rom_size
+                    .map(|x| x.to_string())
+                    .unwrap_or("unknown".to_string()),
+                info.file_size
+            ),
+        );
+        row(
+            "RAM size",
+            info.header_ram_size
+                .map(|x| x.to_string())
+                .unwrap_or("unknown".to_string()),
+        );
+    });
+    if info.problems.is_empty() {
+        ui.label("No problems found");
+    }
+    for problem in &info.problems {
+        ui.colored_label(ui.visuals().error_fg_color, problem);
     }
 }
 
 const TOBU: &[u8] = include_bytes!("../assets/roms/tobu.gb");
 
 impl eframe::App for GbApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.store_battery();
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if let Some(promise) = &self.promise {
-            if let Some(Some(rom)) = promise.ready() {
-                let host = cpal::default_host();
-                let device = host
-                    .default_output_device()
-                    .expect("failed to find a default output device");
-                let config = device.default_output_config().unwrap();
-                let sample_rate = config.sample_rate().0;
-                self.screen = Some(Screen::new(
-                    Cpu::new(rom.clone(), sample_rate).unwrap(),
-                    ctx,
-                ));
-                self.promise = None;
+        if let Some(files) = self.promise.as_ref().and_then(|x| x.ready()).cloned() {
+            self.promise = None;
+            if !files.is_empty() {
+                self.open(files, ctx);
+            }
+        }
+        // battery saves are written back as the game makes them, not just on exit, in case of a crash
+        let now = ctx.input(|i| i.time);
+        if now - self.last_store > 1.0 {
+            self.last_store = now;
+            self.store_battery();
+        }
+        if let Some(file) = self.patch_promise.as_ref().and_then(|x| x.ready()).cloned() {
+            self.patch_promise = None;
+            if let (Some(file), Some(rom)) = (file, self.base_rom.clone()) {
+                // patches stack, so reload the original ROM with this one on top
+                self.patches.push(file);
+                self.load_rom(rom, ctx);
+            }
+        }
+        if let Some(promise) = &self.save_promise {
+            if let Some(save) = promise.ready() {
+                if let (Some(save), Some(screen)) = (save, &mut self.screen) {
+                    if let Err(e) = screen.cpu.mmu.cartridge.mbc.load_save(save) {
+                        log::error!("GbApp: failed to load save: {e}");
+                    }
+                }
+                self.save_promise = None;
+            }
+        }
+        if let Some(promise) = &self.camera_promise {
+            if let Some(files) = promise.ready() {
+                if let Some(screen) = &mut self.screen {
+                    let frames = files
+                        .iter()
+                        .filter_map(|(file, _)| match camera::decode_frame(file) {
+                            Ok(frame) => Some(frame),
+                            Err(e) => {
+                                log::error!("GbApp: failed to decode camera image: {e}");
+                                None
+                            }
+                        })
+                        .collect();
+                    screen.cpu.mmu.cartridge.mbc.set_camera_frames(frames);
+                }
+                self.camera_promise = None;
+            }
+        }
+        if let Some(promise) = &self.export_promise {
+            if promise.ready().is_some() {
+                self.export_promise = None;
             }
         }
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::MenuBar::new().ui(ui, |ui| {
                 ui.menu_button("File", |ui| {
+                    // pick patches along with the ROM to have them applied straight away
                     if ui.button("Open ROM").clicked() {
-                        self.promise = Some(poll_promise::Promise::spawn_local(async {
-                            if let Some(file) = rfd::AsyncFileDialog::new().pick_file().await {
-                                let f = file.read().await;
-                                Some(f)
-                            } else {
-                                None
-                            }
-                        }));
+                        self.promise = Some(open_files());
                     }
                     ui.menu_button("Load Example", |ui| {
                         if ui.button("Tobu Tobu Girl").clicked() {
-                            self.promise =
-                                Some(poll_promise::Promise::from_ready(Some(TOBU.to_vec())))
+                            self.promise = Some(poll_promise::Promise::from_ready(vec![(
+                                TOBU.to_vec(),
+                                None,
+                            )]))
+                        }
+                    });
+                    if ui
+                        .add_enabled(self.base_rom.is_some(), egui::Button::new("Apply Patch"))
+                        .on_hover_text("Restarts the game with an IPS, UPS or BPS patch")
+                        .clicked()
+                    {
+                        self.patch_promise = Some(open_file());
+                    }
+                    if ui
+                        .add_enabled(self.rom_info.is_some(), egui::Button::new("ROM Info"))
+                        .clicked()
+                    {
+                        self.show_rom_info = true;
+                    }
+                    if let Some(screen) = &self.screen {
+                        let save = screen.cpu.mmu.cartridge.mbc.save();
+                        if ui
+                            .add_enabled(save.is_some(), egui::Button::new("Import Save"))
+                            .clicked()
+                        {
+                            self.save_promise = Some(open_file());
+                        }
+                        if ui
+                            .add_enabled(save.is_some(), egui::Button::new("Export Save"))
+                            .clicked()
+                        {
+                            let name = format!(
+                                "{}.sav",
+                                screen.cpu.mmu.cartridge.title.trim_end_matches('\0')
+                            );
+                            self.export_promise = Some(export_file(name, save.unwrap_or_default()));
                         }
-                    })
+                        if matches!(
+                            screen.cpu.mmu.cartridge.cartridge_type,
+                            Mapper::PocketCamera
+                        ) {
+                            if ui.button("Load Camera Images").clicked() {
+                                // several images are played back as a sequence, one per capture
+                                self.camera_promise = Some(open_files());
+                            }
+                            if ui.button("Export Photos").clicked() {
+                                let photos = screen.cpu.mmu.cartridge.mbc.photos();
+                                match camera::encode_photo_sheet(&photos) {
+                                    Ok(png) => {
+                                        self.export_promise =
+                                            Some(export_file("photos.png".to_string(), png))
+                                    }
+                                    Err(e) => log::error!("GbApp: failed to export photos: {e}"),
+                                }
+                            }
+                        }
+                    }
                 });
+                let mut restart = false;
                 if let Some(screen) = &mut self.screen {
                     ui.menu_button("Debug", |ui| {
                         ui.checkbox(&mut screen.debugger.show_vram, "Show VRAM");
+                        ui.checkbox(
+                            &mut screen.cpu.mmu.accurate,
+                            "Lock VRAM/OAM while the PPU uses them",
+                        );
+                        if matches!(
+                            screen.cpu.mmu.cartridge.cartridge_type,
+                            Mapper::HuC1RamBattery | Mapper::HuC3
+                        ) && ui
+                            .checkbox(&mut self.ir_loopback, "IR loopback")
+                            .on_hover_text("The cart receives its own IR LED")
+                            .changed()
+                        {
+                            screen
+                                .cpu
+                                .mmu
+                                .cartridge
+                                .mbc
+                                .connect_ir(if self.ir_loopback {
+                                    IrLink::loopback()
+                                } else {
+                                    IrLink::default()
+                                });
+                        }
+                        ui.menu_button("Power-on RAM", |ui| {
+                            // picking a policy restarts the game, random ones use the typed seed if there is one
+                            let typed = (!self.ram_seed.trim().is_empty())
+                                .then(|| u64::from_str_radix(self.ram_seed.trim(), 16));
+                            for (init, name) in [
+                                (RamInit::Zero, "Zero"),
+                                (RamInit::Ones, "0xff"),
+                                (RamInit::Random(0), "Random"),
+                                (RamInit::Dmg(0), "DMG-like"),
+                            ] {
+                                let selected = std::mem::discriminant(&init)
+                                    == std::mem::discriminant(&self.ram_init);
+                                let enabled =
+                                    init.seed().is_none() || !matches!(typed, Some(Err(_)));
+                                if ui
+                                    .add_enabled(enabled, egui::RadioButton::new(selected, name))
+                                    .clicked()
+                                {
+                                    self.ram_init = init.with_seed(match typed {
+                                        Some(Ok(seed)) => seed,
+                                        _ => RamInit::new_seed(),
+                                    });
+                                    restart = true;
+                                }
+                            }
+                            ui.horizontal(|ui| {
+                                ui.label("Seed");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.ram_seed)
+                                        .hint_text("new each restart")
+                                        .desired_width(140.0),
+                                );
+                                if let Some(seed) = screen.cpu.mmu.ram_init.seed() {
+                                    if ui.small_button("Use current").clicked() {
+                                        self.ram_seed = format!("{seed:x}");
+                                    }
+                                }
+                            });
+                            if matches!(typed, Some(Err(_))) {
+                                ui.colored_label(ui.visuals().error_fg_color, "Seed should be hex");
+                            }
+                            ui.label(format!("Current: {}", screen.cpu.mmu.ram_init));
+                        });
                     });
                 }
+                if restart {
+                    if let Some(rom) = self.base_rom.clone() {
+                        self.load_rom(rom, ctx);
+                    }
+                }
             })
         });
 
+        if let Some(info) = &self.rom_info {
+            egui::Window::new("ROM Info")
+                .open(&mut self.show_rom_info)
+                .show(ctx, |ui| rom_info_ui(ui, info));
+        }
+
+        if let Some((roms, _)) = &self.rom_choices {
+            let mut open = true;
+            let mut chosen = None;
+            egui::Window::new("Choose ROM")
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    ui.label("This archive has more than one ROM in it:");
+                    for (i, entry) in roms.iter().enumerate() {
+                        if ui.button(&entry.name).clicked() {
+                            chosen = Some(i);
+                        }
+                    }
+                });
+            if let Some(i) = chosen {
+                let (mut roms, path) = self.rom_choices.take().unwrap();
+                let entry = roms.swap_remove(i);
+                // each game in the archive gets its own save, named after it
+                let name = std::path::Path::new(&entry.name)
+                    .file_name()
+                    .unwrap_or_default();
+                self.set_save_path(
+                    path.map(|path| path.with_file_name(name).with_extension("sav")),
+                );
+                self.load_rom(entry.rom, ctx);
+            } else if !open {
+                self.rom_choices = None;
+            }
+        }
+
+        if let Some(error) = &self.error {
+            let mut open = true;
+            egui::Window::new("Couldn't load ROM")
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                });
+            if !open {
+                self.error = None;
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             if let Some(screen) = &mut self.screen {
                 ui.vertical_centered(|ui| {
@@ -0,0 +1,150 @@
+use std::io::{Cursor, Read};
+
+use anyhow::anyhow;
+
+/// Largest ROM we'll pull out of an archive, 512 banks. Stops a zip bomb eating all our memory
+const MAX_ROM_SIZE: u64 = 512 * 0x4000;
+const ROM_EXTENSIONS: [&str; 3] = [".gb", ".gbc", ".sgb"];
+
+#[derive(Debug, Clone)]
+pub struct RomEntry {
+    pub name: String,
+    pub rom: Vec<u8>,
+}
+
+/// Unpacks the ROMs from a file, which can be a zip, a gzipped ROM or just a ROM. Zips can
+/// hold several ROMs, in which case they're returned in archive order
+pub fn roms(file: Vec<u8>) -> anyhow::Result<Vec<RomEntry>> {
+    match file.as_slice() {
+        [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x05, 0x06, ..] => unzip(file),
+        [0x1f, 0x8b, ..] => gunzip(file),
+        _ => Ok(vec![RomEntry {
+            name: String::new(),
+            rom: file,
+        }]),
+    }
+}
+
+fn unzip(file: Vec<u8>) -> anyhow::Result<Vec<RomEntry>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(file))?;
+    let mut roms = Vec::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        let name = entry.name().to_string();
+        let lower = name.to_lowercase();
+        if entry.is_dir() || !ROM_EXTENSIONS.iter().any(|ext| lower.ends_with(ext)) {
+            continue;
+        }
+        log::info!("archive: found {name} in zip");
+        roms.push(RomEntry {
+            rom: read_limited(entry, &name)?,
+            name,
+        });
+    }
+    if roms.is_empty() {
+        return Err(anyhow!(
+            "archive: zip doesn't contain any {} files",
+            ROM_EXTENSIONS.join(", ")
+        ));
+    }
+    Ok(roms)
+}
+
+fn gunzip(file: Vec<u8>) -> anyhow::Result<Vec<RomEntry>> {
+    let mut decoder = flate2::read::GzDecoder::new(Cursor::new(file));
+    let rom = read_limited(&mut decoder, "gzip")?;
+    // the original file name is optional in gzip
+    let name = decoder
+        .header()
+        .and_then(|header| header.filename())
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .unwrap_or_default();
+    Ok(vec![RomEntry { name, rom }])
+}
+
+fn read_limited(reader: impl Read, name: &str) -> anyhow::Result<Vec<u8>> {
+    let mut rom = Vec::new();
+    reader.take(MAX_ROM_SIZE + 1).read_to_end(&mut rom)?;
+    if rom.len() as u64 > MAX_ROM_SIZE {
+        return Err(anyhow!(
+            "archive: {name} is bigger than the largest possible ROM"
+        ));
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn gzip(name: Option<&str>, data: &[u8]) -> Vec<u8> {
+        let mut builder = flate2::GzBuilder::new();
+        if let Some(name) = name {
+            builder = builder.filename(name);
+        }
+        let mut gz = builder.write(Vec::new(), flate2::Compression::default());
+        gz.write_all(data).unwrap();
+        gz.finish().unwrap()
+    }
+
+    #[test]
+    fn plain_rom() {
+        let roms = roms(vec![1, 2, 3]).unwrap();
+        assert_eq!(roms.len(), 1);
+        assert_eq!(
+            (roms[0].name.as_str(), roms[0].rom.as_slice()),
+            ("", [1, 2, 3].as_slice())
+        );
+    }
+
+    #[test]
+    fn zip_keeps_roms_in_order() {
+        let file = zip(&[
+            ("b.GBC", &[2]),
+            ("readme.txt", &[0]),
+            ("dir/a.gb", &[1]),
+            ("c.sgb", &[3]),
+        ]);
+        let roms = roms(file).unwrap();
+        let names: Vec<_> = roms.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["b.GBC", "dir/a.gb", "c.sgb"]);
+        assert_eq!(roms[1].rom, [1]);
+    }
+
+    #[test]
+    fn zip_without_roms() {
+        let error = roms(zip(&[("readme.txt", &[0])])).unwrap_err();
+        assert!(error.to_string().contains(".gb, .gbc, .sgb"));
+    }
+
+    #[test]
+    fn gzip_name_is_optional() {
+        let roms_named = roms(gzip(Some("game.gb"), &[1, 2])).unwrap();
+        assert_eq!(roms_named[0].name, "game.gb");
+        assert_eq!(roms_named[0].rom, [1, 2]);
+        assert_eq!(roms(gzip(None, &[1, 2])).unwrap()[0].name, "");
+    }
+
+    #[test]
+    fn too_big() {
+        let big = vec![0; MAX_ROM_SIZE as usize + 1];
+        assert!(roms(gzip(None, &big)).is_err());
+        assert!(roms(zip(&[("big.gb", &big)])).is_err());
+        assert_eq!(
+            roms(gzip(None, &big[1..])).unwrap()[0].rom.len(),
+            big.len() - 1
+        );
+    }
+}
@@ -6,12 +6,11 @@ mod duty_cycle;
 pub mod envelope;
 mod length;
 
-use std::sync::{Arc, RwLock};
-
-use anyhow::anyhow;
-
 use crate::{
-    core::apu::{ch1::Ch1, ch2::Ch2, ch3::Ch3, ch4::Ch4},
+    core::{
+        apu::{ch1::Ch1, ch2::Ch2, ch3::Ch3, ch4::Ch4},
+        error::EmuError,
+    },
     screen::ApuSamples,
 };
 #[derive(Debug, Default)]
@@ -26,10 +25,12 @@ pub struct Apu {
 
     div_apu: u8,
     enabled: bool,
-    sys_old: u16,
     pub cur_sample: ApuSamples,
     sample_rate: u32,
     capacitor: f32,
+    /// What the cartridge's own speaker is playing, HuC3 carts have one
+    pub cart_tone: Option<u8>,
+    cart_phase: u32,
 }
 
 trait Channel {
@@ -54,7 +55,7 @@ impl Apu {
         self.nr50 = 0;
         self.nr51 = 0;
     }
-    pub fn write(&mut self, addr: u16, val: u8, sys: u16) -> anyhow::Result<()> {
+    pub fn write(&mut self, addr: u16, val: u8, sys: u16) -> Result<(), EmuError> {
         log::debug!("Apu: write: [{sys:04x?}] {addr:04x?} = {val:02x?}");
         if self.enabled
             || matches!(
@@ -82,13 +83,19 @@ impl Apu {
                     }
                 }
                 0xff30..=0xff3f => self.ch3.write(self.div_apu, addr, val, self.enabled),
-                _ => return Err(anyhow!("Apu: invalid register write: {addr:04x?}")),
+                _ => {
+                    return Err(EmuError::InvalidApuRegister {
+                        pc: None,
+                        addr,
+                        write: true,
+                    });
+                }
             };
         }
 
         Ok(())
     }
-    pub fn read(&self, addr: u16, sys: u16) -> anyhow::Result<u8> {
+    pub fn read(&self, addr: u16, sys: u16) -> Result<u8, EmuError> {
         let v = match addr {
             0xff10..=0xff14 => self.ch1.read(addr),
             0xff16..=0xff19 => self.ch2.read(addr),
@@ -117,7 +124,13 @@ impl Apu {
                 enabled | 0b0111_0000 | ch_enabled
             }
             0xff30..=0xff3f => self.ch3.read(addr),
-            _ => return Err(anyhow!("Apu: invalid register write: {addr:04x?}")),
+            _ => {
+                return Err(EmuError::InvalidApuRegister {
+                    pc: None,
+                    addr,
+                    write: false,
+                });
+            }
         };
         log::debug!("Apu: read: [{sys:04x?}] {addr:04x?} = {v:02x?}");
 
@@ -131,13 +144,17 @@ impl Apu {
         }
     }
 
-    pub fn clock(&mut self, sys: u16) {
+    /// `div_apu` is set on a falling edge of bit 12 of the system counter, see `Timer`
+    pub fn clock(&mut self, sys: u16, div_apu: bool) {
         // clock every M-cycle
         if sys % 4 == 0 {
             self.ch1.clock_fast();
             self.ch2.clock_fast();
             self.ch3.clock_fast();
             self.ch4.clock_fast();
+            if self.cart_tone.is_some() {
+                self.cart_phase = self.cart_phase.wrapping_add(1);
+            }
         }
         if sys % (4194304 / self.sample_rate) as u16 == 0 {
             let sample = self.sample();
@@ -148,11 +165,7 @@ impl Apu {
             cur_sample.push_back(sample);
         }
 
-        // check for a falling edge
-        let old_set = (self.sys_old & 0b0001_0000_0000_0000) > 0;
-        let cur_unset = (sys & 0b0001_0000_0000_0000) == 0;
-        self.sys_old = sys;
-        if !(old_set && cur_unset) {
+        if !div_apu {
             return;
         }
         log::debug!("Apu: clock! {sys:04x?}");
@@ -223,6 +236,22 @@ impl Apu {
             }
             self.high_pass(mix * (right_volume + 1) as f32 / 8.0)
         };
-        (left, right)
+        // the cart speaker doesn't go through the APU, NR50/NR51 and NR52 can't silence it
+        let cart = self.cart_sample();
+        (left + cart, right + cart)
+    }
+
+    /// Square wave for the cart speaker. Which pitch each tone makes isn't documented, they're
+    /// spread from 512 Hz to 4 kHz
+    fn cart_sample(&self) -> f32 {
+        let Some(tone) = self.cart_tone else {
+            return 0.0;
+        };
+        let half_period = 1_048_576 / (2 * 256 * ((tone as u32 & 0xf) + 1));
+        if (self.cart_phase / half_period) % 2 == 0 {
+            0.25
+        } else {
+            -0.25
+        }
     }
 }
@@ -1,25 +1,35 @@
 pub mod opcodes;
 pub mod register;
-use anyhow::anyhow;
-
 use crate::core::{
     cpu::register::{CpuRegisters, Register},
-    mmu::Mmu,
+    error::EmuError,
+    mmu::{Mmu, OamBug},
     ppu::Ppu,
 };
 
+const M_CYCLES_PER_FRAME: usize = 70224 / 4;
+
 #[derive(Debug)]
 pub struct Cpu {
     registers: CpuRegisters,
     pub mmu: Mmu,
-    delay: usize,
     pub cycles: usize,
     pub ppu: Ppu,
     ime: bool,
+    /// EI was just executed, IME is set after the next instruction
+    ei_pending: bool,
     pub logging: bool,
     pub halted: bool,
+    /// HALT with IME=0 and an interrupt pending, the next opcode fetch doesn't increment PC
+    halt_bug: bool,
+    /// STOP low-power mode, everything is frozen until one of the selected joypad lines goes low
+    pub stopped: bool,
+    /// The illegal opcode that hard-locked the CPU, only a reset gets it going again
+    pub locked: Option<u8>,
+    /// Bytes OAM DMA still has to copy
     pub dma_idx: u8,
-    pub timer_overflow: bool,
+    /// Page of an OAM DMA that starts next cycle, a running one keeps going until then
+    dma_pending: Option<u8>,
 }
 
 impl Cpu {
@@ -27,14 +37,17 @@ impl Cpu {
         let cpu = Cpu {
             registers: CpuRegisters::default(),
             mmu: Mmu::new(rom, sample_rate)?,
-            delay: 0,
             cycles: 0,
             ppu: Ppu::new(),
             ime: false,
+            ei_pending: false,
             logging: false,
             halted: false,
+            halt_bug: false,
+            stopped: false,
+            locked: None,
             dma_idx: 0,
-            timer_overflow: true,
+            dma_pending: None,
         };
         Ok(cpu)
     }
@@ -48,134 +61,238 @@ impl Cpu {
         cpu.registers.pc.write(0x0100);
         cpu.registers.sp.write(0xfffe);
         cpu.mmu.io.bank = 0xff;
-        cpu.mmu.sys = 0xabcc;
+        cpu.mmu.io.lcdc = 0x91;
+        cpu.mmu.timer.sys = 0xabcc;
         Ok(cpu)
     }
 
-    pub fn call_interrupt(&mut self, addr: u16, b: u8) -> anyhow::Result<()> {
-        self.mmu.io.interrupt &= !(1 << b);
-        self.ime = false;
-        self.mmu
-            .write(self.registers.sp.read() - 1, self.registers.pc.high.read())?;
-        self.mmu
-            .write(self.registers.sp.read() - 2, self.registers.pc.low.read())?;
-        self.registers.sp -= 2;
-        self.registers.pc.write(addr);
-        self.halted = false;
-        self.delay += 5;
+    /// Runs one frame's worth of T-cycles, any overshoot from the last instruction carries into the next frame
+    pub fn frame(&mut self) -> Result<(), EmuError> {
+        let end = (self.cycles / M_CYCLES_PER_FRAME + 1) * M_CYCLES_PER_FRAME;
+        while self.cycles < end {
+            self.cycle()?;
+        }
         Ok(())
     }
 
-    pub fn cycle(&mut self) -> anyhow::Result<()> {
-        if self.mmu.sys % 4 != 0 {
-            // only clock the cpu on an m-cycle
-            return Ok(());
-        }
-        if self.logging {
-            println!(
-                "SYS: {} IME: {} IE: {:x?} IF: {:x?} TIMA: {:x?} TAC: {:x?} TMA: {:x?} DIV: {:x?}",
-                self.mmu.sys,
-                self.ime,
-                self.mmu.ie,
-                self.mmu.io.interrupt,
-                self.mmu.io.tima,
-                self.mmu.io.tac,
-                self.mmu.io.tma,
-                self.mmu.read(0xff04)?,
-            );
+    /// Runs a frame on this and a second Cpu, an instruction at a time each so they stay within one
+    /// instruction of each other. IR (see `IrLink::pair`) only works when its pulses arrive on time,
+    /// a frame on one and then a frame on the other would deliver them up to a frame late
+    pub fn frame_linked(&mut self, other: &mut Cpu) -> Result<(), EmuError> {
+        let (start, other_start) = (self.cycles, other.cycles);
+        let len = (self.cycles / M_CYCLES_PER_FRAME + 1) * M_CYCLES_PER_FRAME - start;
+        loop {
+            let ours = self.cycles - start;
+            let theirs = other.cycles - other_start;
+            if ours >= len && theirs >= len {
+                return Ok(());
+            }
+            // whoever is behind goes next
+            if ours < len && (ours <= theirs || theirs >= len) {
+                self.cycle()?;
+            } else {
+                other.cycle()?;
+            }
         }
-        if self.timer_overflow {
-            // should be delayed by one m-cycle
+    }
+
+    /// Advances everything but the CPU by one M-cycle
+    pub fn tick(&mut self) -> Result<(), EmuError> {
+        if self.mmu.timer.m_cycle() {
             self.mmu.io.interrupt |= 0b00000100;
-            self.mmu.io.tima = self.mmu.io.tma;
-            self.timer_overflow = false;
         }
-        if (self.mmu.io.tac & 0b00000100) > 0 {
-            // timer is enabled, tick it
-            let interval = match self.mmu.io.tac & 0b0000_0011 {
-                0b00 => 1024,
-                0b01 => 16,
-                0b10 => 64,
-                0b11 => 256,
-                _ => unreachable!(),
-            };
 
-            if self.mmu.sys % interval == 0 {
-                let (val, overflow) = self.mmu.io.tima.overflowing_add(1);
-                self.mmu.io.tima = val;
-                if overflow {
-                    log::debug!("timer overflow");
-                    self.timer_overflow = true;
-                }
-            }
-        }
+        self.mmu.cartridge.mbc.clock();
+        self.mmu.apu.cart_tone = self.mmu.cartridge.mbc.tone();
 
+        if let Some(page) = self.dma_pending.take() {
+            log::debug!("cycle: DMA: starting transfer from 0x{page:02x?}00");
+            self.mmu.start_dma(page);
+            self.dma_idx = 160;
+        }
         if self.mmu.dma_requsted {
-            self.dma_idx = 161; // TODO: verify
+            // there's a cycle of delay before the copy starts (or restarts)
             self.mmu.dma_requsted = false;
-            log::debug!("cycle: DMA: starting transfer")
+            self.dma_pending = Some(self.mmu.io.dma);
         }
 
-        if self.dma_idx > 160 {
-            // apparently there's 2 delay cycles
-            self.dma_idx -= 1;
-        } else if self.dma_idx > 0 {
+        if self.dma_idx > 0 {
             let offset = 160 - self.dma_idx as u16;
-            let (src, _) = ((self.mmu.io.dma as u16) << 8).overflowing_add(offset);
-            let (dest, _) = 0xfe00u16.overflowing_add(offset); // 0xfe00 is the base address for OAM
-            self.mmu.write(dest, self.mmu.read(src)?)?;
+            let val = self.mmu.dma_read(offset)?;
+            self.mmu.dma_write(offset, val);
             self.dma_idx -= 1;
-            log::trace!("cycle: DMA: copied from 0x{src:04x?} to 0x{dest:04x?}");
+            log::trace!("cycle: DMA: copied 0x{val:02x?} to OAM offset 0x{offset:02x?}");
         }
+        self.mmu.dma_active = self.dma_idx > 0;
+
+        for _ in 0..4 {
+            let div_apu = self.mmu.timer.div_apu_edge();
+            self.mmu.apu.clock(self.mmu.timer.sys, div_apu);
+            self.ppu.clock(&mut self.mmu)?;
 
+            self.mmu.timer.clock();
+        }
         self.cycles += 1;
-        match self.delay {
-            0 => {}
-            1 => {
-                self.delay -= 1;
+        Ok(())
+    }
+
+    /// Reads from the bus, taking one M-cycle
+    pub fn bus_read(&mut self, addr: u16) -> Result<u8, EmuError> {
+        self.tick()?;
+        self.mmu.read(addr)
+    }
+
+    /// Reads through a register pair, which corrupts OAM if it points there while the PPU is scanning it
+    pub fn bus_read_ptr(&mut self, addr: u16) -> Result<u8, EmuError> {
+        let val = self.bus_read(addr)?;
+        self.oam_bug(addr, OamBug::Read);
+        Ok(val)
+    }
+
+    /// Writes to the bus, taking one M-cycle
+    pub fn bus_write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
+        self.tick()?;
+        self.mmu.write(addr, val)
+    }
+
+    /// DMG OAM corruption bug, the CPU put an OAM address on the bus while the PPU was scanning OAM
+    pub fn oam_bug(&mut self, addr: u16, kind: OamBug) {
+        if !self.mmu.accurate || !(0xfe00..=0xfeff).contains(&addr) {
+            return;
+        }
+        if let Some(row) = self.ppu.oam_row(&self.mmu) {
+            log::warn!(
+                "cpu: OAM corrupted ({kind:?}) by 0x{addr:04x?} at pc 0x{:04x?}",
+                self.registers.pc.read()
+            );
+            self.mmu.oam_bug(row, kind);
+        }
+    }
+
+    /// Register dump for error reports, doesn't touch memory so it works whatever state we're in
+    pub fn state(&self) -> String {
+        format!(
+            "AF: {:04X?} BC: {:04X?} DE: {:04X?} HL: {:04X?} SP: {:04X?} PC: {:04X?} IME: {} IE: {:02X?} IF: {:02X?} halted: {} stopped: {} locked: {} cycles: {}",
+            self.registers.af.read(),
+            self.registers.bc.read(),
+            self.registers.de.read(),
+            self.registers.hl.read(),
+            self.registers.sp.read(),
+            self.registers.pc.read(),
+            self.ime,
+            self.mmu.ie,
+            self.mmu.io.interrupt,
+            self.halted,
+            self.stopped,
+            self.locked.is_some(),
+            self.cycles,
+        )
+    }
+
+    /// Hook for the CGB double speed switch armed through KEY1. The DMG has no KEY1, so STOP always goes to sleep
+    fn speed_switch(&mut self) -> bool {
+        false
+    }
+
+    /// Interrupts pending and enabled in IE, the upper 3 bits of IF/IE aren't wired to anything
+    fn pending_interrupts(&self) -> u8 {
+        self.mmu.ie & self.mmu.io.interrupt & 0b0001_1111
+    }
+
+    pub fn call_interrupt(&mut self) -> Result<(), EmuError> {
+        self.ime = false;
+        self.halted = false;
+        if self.halt_bug {
+            // EI right before HALT, the handler returns to the HALT
+            self.halt_bug = false;
+            self.registers.pc -= 1;
+        }
+        self.tick()?;
+        self.tick()?;
+        let sp = self.registers.sp.read();
+        self.bus_write(sp.wrapping_sub(1), self.registers.pc.high.read())?;
+        // the interrupt is only picked after the high byte is pushed, that push can land on IE at 0xffff and
+        // change the outcome. If nothing is left pending the CPU jumps to 0x0000 without acknowledging anything
+        let addr = match self.pending_interrupts() {
+            0 => {
+                log::debug!("cycle: interrupt cancelled by IE push");
+                0x0000
             }
-            _ => {
-                self.delay -= 1;
-                return Ok(());
+            pending => {
+                let b = pending.trailing_zeros() as u16;
+                log::debug!(
+                    "cycle: servicing {} interrupt",
+                    ["vblank", "lcd", "timer", "serial", "joypad"][b as usize]
+                );
+                self.mmu.io.interrupt &= !(1 << b);
+                0x40 + b * 8
             }
+        };
+        self.bus_write(sp.wrapping_sub(2), self.registers.pc.low.read())?;
+        self.registers.sp -= 2;
+        self.registers.pc.write(addr);
+        self.tick()?;
+        Ok(())
+    }
+
+    pub fn cycle(&mut self) -> Result<(), EmuError> {
+        let pc = self.registers.pc.read();
+        self.step().map_err(|e| e.at_pc(pc))
+    }
+
+    fn step(&mut self) -> Result<(), EmuError> {
+        if self.logging {
+            println!(
+                "SYS: {} IME: {} IE: {:x?} IF: {:x?} TIMA: {:x?} TAC: {:x?} TMA: {:x?} DIV: {:x?}",
+                self.mmu.timer.sys,
+                self.ime,
+                self.mmu.ie,
+                self.mmu.io.interrupt,
+                self.mmu.timer.tima,
+                self.mmu.timer.tac,
+                self.mmu.timer.tma,
+                self.mmu.read(0xff04)?,
+            );
         }
 
         log::trace!("cycle: cpu state: {:?}", self.registers);
 
-        if self.ime && (self.mmu.ie & self.mmu.io.interrupt) > 0 {
-            // interrupts are enabled and at least one has been requested
-            log::debug!("interrupts are enabled and one has been requested");
-            if (self.mmu.ie & self.mmu.io.interrupt & 0b00000001) > 0 {
-                // vblank
-                log::debug!("cycle: servicing vblank interrupt");
-                self.call_interrupt(0x40, 0)?;
-            } else if (self.mmu.ie & self.mmu.io.interrupt & 0b00000010) > 0 {
-                // lcd
-                log::debug!("cycle: servicing lcd interrupt");
-                self.call_interrupt(0x48, 1)?;
-            } else if (self.mmu.ie & self.mmu.io.interrupt & 0b00000100) > 0 {
-                // timer
-                log::debug!("cycle: servicing timer interrupt");
-                self.call_interrupt(0x50, 2)?;
-            } else if (self.mmu.ie & self.mmu.io.interrupt & 0b00001000) > 0 {
-                // serial
-                log::debug!("cycle: servicing serial interrupt");
-                self.call_interrupt(0x58, 3)?;
-            } else if (self.mmu.ie & self.mmu.io.interrupt & 0b00010000) > 0 {
-                // joypad
-                log::debug!("cycle: servicing joypad interrupt");
-                self.call_interrupt(0x60, 4)?;
+        if self.locked.is_some() {
+            // the rest of the system keeps running, the CPU never fetches again
+            return self.tick();
+        }
+
+        if self.stopped {
+            if self.mmu.joypad_lines() == 0b1111 {
+                // the system clock is stopped too, only count cycles so frames keep coming
+                self.cycles += 1;
+                return Ok(());
             }
+            log::debug!("cycle: woken up from STOP");
+            self.stopped = false;
+        }
+
+        if self.ime && self.pending_interrupts() > 0 {
+            self.call_interrupt()?;
+            // the handler's first opcode is fetched on the next step
+            return Ok(());
         }
 
         if self.halted {
-            if (self.mmu.ie & self.mmu.io.interrupt) > 0 {
+            if self.pending_interrupts() > 0 {
                 self.halted = false;
             }
-            return Ok(());
+            return self.tick();
         }
 
-        let opcode = self.mmu.read(self.registers.pc.read())?;
+        // set by the previous instruction, takes effect once this one is done unless it was a DI
+        let ei = self.ei_pending;
+        let opcode = self.bus_read(self.registers.pc.read())?;
+        if self.halt_bug {
+            // the opcode is read again as the next byte, shifting everything after it by one
+            self.halt_bug = false;
+            self.registers.pc -= 1;
+        }
         log::trace!(
             "cycle: opcode 0x{opcode:x?}, pc: 0x{:x?}",
             self.registers.pc.read()
@@ -194,9 +311,9 @@ impl Cpu {
                 self.registers.sp.read(),
                 self.registers.pc.read(),
                 self.mmu.read(self.registers.pc.read())?,
-                self.mmu.read(self.registers.pc.read() + 1)?,
-                self.mmu.read(self.registers.pc.read() + 2)?,
-                self.mmu.read(self.registers.pc.read() + 3)?,
+                self.mmu.read(self.registers.pc.read().wrapping_add(1))?,
+                self.mmu.read(self.registers.pc.read().wrapping_add(2))?,
+                self.mmu.read(self.registers.pc.read().wrapping_add(3))?,
                 self.mmu.read(self.registers.hl.read())?
             );
         }
@@ -292,8 +409,12 @@ impl Cpu {
             0xa6 => self.and_a_ptr_hl()?,
             0x27 => self.daa()?,
             0x76 => self.halt()?,
+            0x10 => self.stop()?,
+            0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb | 0xec | 0xed | 0xf4 | 0xfc | 0xfd => {
+                self.illegal(opcode)?
+            }
             0xcb => {
-                let opcode = self.mmu.read(self.registers.pc.read() + 1)?;
+                let opcode = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
                 match opcode {
                     0x40..=0x45
                     | 0x47..=0x4d
@@ -362,9 +483,10 @@ impl Cpu {
                     }
                 }
             }?,
-            _ => {
-                return Err(anyhow!("cycle: unknown opcode: {opcode:x?}"));
-            }
+        }
+        if ei && self.ei_pending {
+            self.ei_pending = false;
+            self.ime = true;
         }
         Ok(())
     }
@@ -1,19 +1,22 @@
-use crate::core::cpu::{Cpu, register::Register};
+use crate::core::{
+    cpu::{Cpu, register::Register},
+    error::EmuError,
+    mmu::OamBug,
+};
 
 impl Cpu {
-    pub fn ld_r16_u16(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn ld_r16_u16(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00110000) >> 4;
-        let arg_high = self.mmu.read(self.registers.pc.read() + 2)? as u16;
-        let arg_low = self.mmu.read(self.registers.pc.read() + 1)? as u16;
+        let arg_low = self.bus_read(self.registers.pc.read().wrapping_add(1))? as u16;
+        let arg_high = self.bus_read(self.registers.pc.read().wrapping_add(2))? as u16;
         let arg = (arg_high << 8) | arg_low;
         log::trace!("ld_r16_u16: {arg:x?}");
         self.registers.get_r16_ss(reg).write(arg);
         self.registers.pc += 3;
-        self.delay += 3;
         Ok(())
     }
 
-    pub fn xor_a_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn xor_a_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let a = self.registers.af.high.read();
         let target = self.registers.get_r8(reg).read();
@@ -26,41 +29,39 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn ld_ptr_hli_a(&mut self) -> anyhow::Result<()> {
+    pub fn ld_ptr_hli_a(&mut self) -> Result<(), EmuError> {
         let addr: u16 = self.registers.hl.into();
-        self.mmu.write(addr, self.registers.af.high.into())?;
+        self.bus_write(addr, self.registers.af.high.into())?;
+        self.oam_bug(addr, OamBug::Write);
         self.registers.hl += 1;
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
-    pub fn ld_ptr_hld_a(&mut self) -> anyhow::Result<()> {
+    pub fn ld_ptr_hld_a(&mut self) -> Result<(), EmuError> {
         let addr: u16 = self.registers.hl.into();
-        self.mmu.write(addr, self.registers.af.high.into())?;
+        self.bus_write(addr, self.registers.af.high.into())?;
+        self.oam_bug(addr, OamBug::Write);
         self.registers.hl -= 1;
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn bit_b_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn bit_b_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let register = opcode & 0b00000111;
         let bit = (opcode & 0b00111000) >> 3;
         let target = self.registers.get_r8(register);
         self.registers.af.low.z = (target.read() & (1 << bit)) == 0;
         self.registers.af.low.h = true;
         self.registers.af.low.n = false;
-        self.delay += 2;
         self.registers.pc += 2;
         Ok(())
     }
 
-    pub fn jr_cond_i8(&mut self, opcode: u8) -> anyhow::Result<()> {
-        let offset: u8 = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn jr_cond_i8(&mut self, opcode: u8) -> Result<(), EmuError> {
+        let offset: u8 = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let offset = offset as i8 as i16;
         let code = (opcode & 0b00011000) >> 3;
         let cond = self.registers.get_cond(code);
@@ -70,32 +71,28 @@ impl Cpu {
             let pc = self.registers.pc.read();
             let (pc, _) = pc.overflowing_add_signed(offset);
             self.registers.pc.write(pc);
-            self.delay += 3;
-        } else {
-            self.delay += 2;
+            self.tick()?;
         }
         Ok(())
     }
 
-    pub fn ld_r8_u8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn ld_r8_u8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00111000) >> 3;
-        let arg = self.mmu.read(self.registers.pc.read() + 1)?;
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         self.registers.get_r8(reg).write(arg);
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn ld_ptr_ff00_c_a(&mut self) -> anyhow::Result<()> {
+    pub fn ld_ptr_ff00_c_a(&mut self) -> Result<(), EmuError> {
         let addr = 0xff00 | (self.registers.bc.low.read() as u16);
         log::trace!("ld_ff00_c_a: writing {addr:x?}");
-        self.mmu.write(addr, self.registers.af.high.read())?;
+        self.bus_write(addr, self.registers.af.high.read())?;
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn inc_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn inc_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00111000) >> 3;
         let target = self.registers.get_r8(reg);
         let val = target.read();
@@ -105,62 +102,64 @@ impl Cpu {
         self.registers.af.low.n = false;
         self.registers.af.low.h = (val & 0x0f) + 1 > 0x0f;
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn ld_ptr_hl_r(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn ld_ptr_hl_r(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let addr = self.registers.hl.read();
-        self.mmu.write(addr, self.registers.get_r8(reg).read())?;
+        let val = self.registers.get_r8(reg).read();
+        self.bus_write(addr, val)?;
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn ld_ptr_ff00_u8_a(&mut self) -> anyhow::Result<()> {
-        let offset = self.mmu.read(self.registers.pc.read() + 1)? as u16;
+    pub fn ld_ptr_ff00_u8_a(&mut self) -> Result<(), EmuError> {
+        let offset = self.bus_read(self.registers.pc.read().wrapping_add(1))? as u16;
         let addr = 0xff00 | offset;
         log::trace!("ld_ff00_u8_a: writing {addr:x?}");
-        self.mmu.write(addr, self.registers.af.high.read())?;
+        self.bus_write(addr, self.registers.af.high.read())?;
         self.registers.pc += 2;
-        self.delay += 3;
         Ok(())
     }
 
-    pub fn ld_a_ptr_bc(&mut self) -> anyhow::Result<()> {
+    pub fn ld_a_ptr_bc(&mut self) -> Result<(), EmuError> {
         let addr = self.registers.bc.read();
-        self.registers.af.high.write(self.mmu.read(addr)?);
+        let val = self.bus_read_ptr(addr)?;
+        self.registers.af.high.write(val);
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn ld_a_ptr_de(&mut self) -> anyhow::Result<()> {
+    pub fn ld_a_ptr_de(&mut self) -> Result<(), EmuError> {
         let addr = self.registers.de.read();
-        self.registers.af.high.write(self.mmu.read(addr)?);
+        let val = self.bus_read_ptr(addr)?;
+        self.registers.af.high.write(val);
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn call_u16(&mut self) -> anyhow::Result<()> {
-        let addr_high = self.mmu.read(self.registers.pc.read() + 2)? as u16;
-        let addr_low = self.mmu.read(self.registers.pc.read() + 1)? as u16;
+    pub fn call_u16(&mut self) -> Result<(), EmuError> {
+        let addr_low = self.bus_read(self.registers.pc.read().wrapping_add(1))? as u16;
+        let addr_high = self.bus_read(self.registers.pc.read().wrapping_add(2))? as u16;
         let addr = (addr_high << 8) | addr_low;
         self.registers.pc += 3;
-        self.mmu
-            .write(self.registers.sp.read() - 1, self.registers.pc.high.read())?;
-        self.mmu
-            .write(self.registers.sp.read() - 2, self.registers.pc.low.read())?;
+        self.tick()?;
+        self.bus_write(
+            self.registers.sp.read().wrapping_sub(1),
+            self.registers.pc.high.read(),
+        )?;
+        self.bus_write(
+            self.registers.sp.read().wrapping_sub(2),
+            self.registers.pc.low.read(),
+        )?;
         self.registers.pc.write(addr);
         self.registers.sp -= 2;
-        self.delay += 6;
         log::trace!("call_u16: calling subroutine 0x{addr:x?}");
         Ok(())
     }
 
-    pub fn ld_r8_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn ld_r8_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let dest = (opcode & 0b00111000) >> 3;
         let src = opcode & 0b00000111;
 
@@ -172,24 +171,28 @@ impl Cpu {
         dest.write(src_value);
 
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn push_r16(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn push_r16(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00110000) >> 4;
         let val = self.registers.get_r16_qq(reg).read();
         let high = ((val & 0xff00) >> 8) as u8;
         let low = (val & 0x00ff) as u8;
-        self.mmu.write(self.registers.sp.read() - 1, high)?;
-        self.mmu.write(self.registers.sp.read() - 2, low)?;
+        let sp = self.registers.sp.read();
+        // SP is decremented on every cycle, each one can corrupt OAM
+        self.tick()?;
+        self.oam_bug(sp, OamBug::Write);
+        self.bus_write(sp.wrapping_sub(1), high)?;
+        self.oam_bug(sp.wrapping_sub(1), OamBug::Write);
+        self.bus_write(sp.wrapping_sub(2), low)?;
+        self.oam_bug(sp.wrapping_sub(2), OamBug::Write);
         self.registers.sp -= 2;
         self.registers.pc += 1;
-        self.delay += 4;
         Ok(())
     }
 
-    pub fn rl_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn rl_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let val = self.registers.get_r8(reg).read();
         let b7 = (val & 0b10000000) > 0;
@@ -209,11 +212,10 @@ impl Cpu {
         self.registers.af.low.c = b7;
 
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn rla(&mut self) -> anyhow::Result<()> {
+    pub fn rla(&mut self) -> Result<(), EmuError> {
         let val = self.registers.af.high.read();
         let b7 = (val & 0b10000000) > 0;
         let val = {
@@ -232,23 +234,24 @@ impl Cpu {
         self.registers.af.low.c = b7;
 
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn pop_r16(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn pop_r16(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00110000) >> 4;
-        let low = self.mmu.read(self.registers.sp.read())?;
-        let high = self.mmu.read(self.registers.sp.read() + 1)?;
+        let sp = self.registers.sp.read();
+        let low = self.bus_read(sp)?;
+        self.oam_bug(sp, OamBug::ReadIncrease);
+        let high = self.bus_read(sp.wrapping_add(1))?;
+        self.oam_bug(sp.wrapping_add(1), OamBug::ReadIncrease);
         let val = ((high as u16) << 8) | (low as u16);
         self.registers.get_r16_qq(reg).write(val);
         self.registers.sp += 2;
         self.registers.pc += 1;
-        self.delay += 3;
         Ok(())
     }
 
-    pub fn dec_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn dec_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00111000) >> 3;
         let target = self.registers.get_r8(reg);
         let val = target.read();
@@ -261,33 +264,33 @@ impl Cpu {
             (x & 0x10) > 0
         };
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn inc_r16(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn inc_r16(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00110000) >> 4;
         let target = self.registers.get_r16_ss(reg);
         let val = target.read();
         let (new_val, _) = val.overflowing_add(1);
         target.write(new_val);
         self.registers.pc += 1;
-        self.delay += 2;
+        self.tick()?;
+        self.oam_bug(val, OamBug::Write);
         Ok(())
     }
 
-    pub fn ret(&mut self) -> anyhow::Result<()> {
-        let low = self.mmu.read(self.registers.sp.read())?;
-        let high = self.mmu.read(self.registers.sp.read() + 1)?;
+    pub fn ret(&mut self) -> Result<(), EmuError> {
+        let low = self.bus_read(self.registers.sp.read())?;
+        let high = self.bus_read(self.registers.sp.read().wrapping_add(1))?;
         self.registers.pc.high.write(high);
         self.registers.pc.low.write(low);
         self.registers.sp += 2;
-        self.delay += 4;
+        self.tick()?;
         Ok(())
     }
 
-    pub fn cp_a_u8(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn cp_a_u8(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let a = self.registers.af.high.read();
 
         self.registers.af.low.z = a == arg;
@@ -299,12 +302,11 @@ impl Cpu {
         };
 
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn cp_a_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.hl.read())?;
+    pub fn cp_a_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read_ptr(self.registers.hl.read())?;
         let a = self.registers.af.high.read();
 
         self.registers.af.low.z = a == arg;
@@ -316,22 +318,20 @@ impl Cpu {
         };
 
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn ld_ptr_u16_a(&mut self) -> anyhow::Result<()> {
-        let low = self.mmu.read(self.registers.pc.read() + 1)?;
-        let high = self.mmu.read(self.registers.pc.read() + 2)?;
+    pub fn ld_ptr_u16_a(&mut self) -> Result<(), EmuError> {
+        let low = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
+        let high = self.bus_read(self.registers.pc.read().wrapping_add(2))?;
         let addr = ((high as u16) << 8) | (low as u16);
-        self.mmu.write(addr, self.registers.af.high.read())?;
+        self.bus_write(addr, self.registers.af.high.read())?;
         self.registers.pc += 3;
-        self.delay += 4;
         Ok(())
     }
 
-    pub fn jr_i8(&mut self) -> anyhow::Result<()> {
-        let offset: u8 = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn jr_i8(&mut self) -> Result<(), EmuError> {
+        let offset: u8 = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let offset = offset as i8 as i16;
         log::trace!("jr_i8: unconditional jump to {offset}");
         self.registers.pc += 2;
@@ -339,25 +339,23 @@ impl Cpu {
         let pc = self.registers.pc.read();
         let (pc, _) = pc.overflowing_add_signed(offset);
         self.registers.pc.write(pc);
-
-        self.delay += 3;
+        self.tick()?;
 
         Ok(())
     }
 
-    pub fn ld_a_ptr_ff00_u8(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.pc.read() + 1)? as u16;
+    pub fn ld_a_ptr_ff00_u8(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))? as u16;
         let addr = 0xff00 | arg;
-        let val = self.mmu.read(addr)?;
+        let val = self.bus_read(addr)?;
         self.registers.af.high.write(val);
 
         self.registers.pc += 2;
-        self.delay += 3;
 
         Ok(())
     }
 
-    pub fn sub_a_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn sub_a_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let arg = self.registers.get_r8(reg).read();
 
@@ -375,12 +373,11 @@ impl Cpu {
         self.registers.af.high.write(val);
 
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn add_a_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.hl.read())?;
+    pub fn add_a_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read_ptr(self.registers.hl.read())?;
         let a = self.registers.af.high.read();
 
         self.registers.af.low.n = false;
@@ -395,83 +392,79 @@ impl Cpu {
         self.registers.af.low.z = val == 0;
         self.registers.pc += 1;
 
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn nop(&mut self) -> anyhow::Result<()> {
+    pub fn nop(&mut self) -> Result<(), EmuError> {
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn jp_u16(&mut self) -> anyhow::Result<()> {
-        let low = self.mmu.read(self.registers.pc.read() + 1)?;
-        let high = self.mmu.read(self.registers.pc.read() + 2)?;
+    pub fn jp_u16(&mut self) -> Result<(), EmuError> {
+        let low = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
+        let high = self.bus_read(self.registers.pc.read().wrapping_add(2))?;
         let addr = ((high as u16) << 8) | (low as u16);
         self.registers.pc.write(addr);
-        self.delay += 4;
+        self.tick()?;
         Ok(())
     }
 
-    pub fn ld_a_ptr_hli(&mut self) -> anyhow::Result<()> {
-        let val = self.mmu.read(self.registers.hl.read())?;
+    pub fn ld_a_ptr_hli(&mut self) -> Result<(), EmuError> {
+        let addr = self.registers.hl.read();
+        let val = self.bus_read(addr)?;
+        self.oam_bug(addr, OamBug::ReadIncrease);
         self.registers.af.high.write(val);
         self.registers.hl += 1;
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
-    pub fn ld_a_ptr_hld(&mut self) -> anyhow::Result<()> {
-        let val = self.mmu.read(self.registers.hl.read())?;
+    pub fn ld_a_ptr_hld(&mut self) -> Result<(), EmuError> {
+        let addr = self.registers.hl.read();
+        let val = self.bus_read(addr)?;
+        self.oam_bug(addr, OamBug::ReadIncrease);
         self.registers.af.high.write(val);
         self.registers.hl -= 1;
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
-    pub fn ld_ptr_de_a(&mut self) -> anyhow::Result<()> {
-        self.mmu
-            .write(self.registers.de.read(), self.registers.af.high.read())?;
+    pub fn ld_ptr_de_a(&mut self) -> Result<(), EmuError> {
+        self.bus_write(self.registers.de.read(), self.registers.af.high.read())?;
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
-    pub fn ld_ptr_bc_a(&mut self) -> anyhow::Result<()> {
-        self.mmu
-            .write(self.registers.bc.read(), self.registers.af.high.read())?;
+    pub fn ld_ptr_bc_a(&mut self) -> Result<(), EmuError> {
+        self.bus_write(self.registers.bc.read(), self.registers.af.high.read())?;
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn di(&mut self) -> anyhow::Result<()> {
+    pub fn di(&mut self) -> Result<(), EmuError> {
         log::debug!("DI");
         self.ime = false;
+        self.ei_pending = false;
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
-    pub fn ld_ptr_hl_u8(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.pc.read() + 1)?;
-        self.mmu.write(self.registers.hl.read(), arg)?;
+    pub fn ld_ptr_hl_u8(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
+        self.bus_write(self.registers.hl.read(), arg)?;
         self.registers.pc += 2;
-        self.delay += 3;
         Ok(())
     }
 
-    pub fn dec_r16(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn dec_r16(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00110000) >> 4;
         let target = self.registers.get_r16_ss(reg);
         let val = target.read();
-        let (val, _) = val.overflowing_sub(1);
-        target.write(val);
+        let (new_val, _) = val.overflowing_sub(1);
+        target.write(new_val);
         self.registers.pc += 1;
-        self.delay += 2;
+        self.tick()?;
+        self.oam_bug(val, OamBug::Write);
         Ok(())
     }
 
-    pub fn or_a_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn or_a_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
 
         let target = {
@@ -486,28 +479,25 @@ impl Cpu {
         self.registers.af.low.n = false;
         self.registers.af.high.write(val);
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
-    pub fn ei(&mut self) -> anyhow::Result<()> {
+    pub fn ei(&mut self) -> Result<(), EmuError> {
         log::debug!("EI");
-        self.ime = true;
+        self.ei_pending = true;
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn cpl(&mut self) -> anyhow::Result<()> {
+    pub fn cpl(&mut self) -> Result<(), EmuError> {
         let val = self.registers.af.high.read();
         self.registers.af.high.write(!val);
         self.registers.af.low.h = true;
         self.registers.af.low.n = true;
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
-    pub fn and_a_u8(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn and_a_u8(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let a = self.registers.af.high.read();
         let val = a & arg;
         self.registers.af.high.write(val);
@@ -516,50 +506,50 @@ impl Cpu {
         self.registers.af.low.h = true;
         self.registers.af.low.c = false;
         self.registers.pc += 2;
-        self.delay += 2;
 
         Ok(())
     }
 
-    pub fn ld_a_ptr_u16(&mut self) -> anyhow::Result<()> {
-        let low = self.mmu.read(self.registers.pc.read() + 1)?;
-        let high = self.mmu.read(self.registers.pc.read() + 2)?;
+    pub fn ld_a_ptr_u16(&mut self) -> Result<(), EmuError> {
+        let low = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
+        let high = self.bus_read(self.registers.pc.read().wrapping_add(2))?;
         let addr = ((high as u16) << 8) | (low as u16);
-        let val = self.mmu.read(addr)?;
+        let val = self.bus_read(addr)?;
         self.registers.af.high.write(val);
         self.registers.pc += 3;
-        self.delay += 4;
 
         Ok(())
     }
 
-    pub fn call_cond_u16(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn call_cond_u16(&mut self, opcode: u8) -> Result<(), EmuError> {
         let code = (opcode & 0b00011000) >> 3;
         let cond = self.registers.get_cond(code);
 
+        let addr_low = self.bus_read(self.registers.pc.read().wrapping_add(1))? as u16;
+        let addr_high = self.bus_read(self.registers.pc.read().wrapping_add(2))? as u16;
+        let addr = (addr_high << 8) | addr_low;
+        self.registers.pc += 3;
         if !cond {
-            self.registers.pc += 3;
-            self.delay += 3;
             return Ok(());
         }
 
-        let addr_high = self.mmu.read(self.registers.pc.read() + 2)? as u16;
-        let addr_low = self.mmu.read(self.registers.pc.read() + 1)? as u16;
-        let addr = (addr_high << 8) | addr_low;
-        self.registers.pc += 3;
-        self.mmu
-            .write(self.registers.sp.read() - 1, self.registers.pc.high.read())?;
-        self.mmu
-            .write(self.registers.sp.read() - 2, self.registers.pc.low.read())?;
+        self.tick()?;
+        self.bus_write(
+            self.registers.sp.read().wrapping_sub(1),
+            self.registers.pc.high.read(),
+        )?;
+        self.bus_write(
+            self.registers.sp.read().wrapping_sub(2),
+            self.registers.pc.low.read(),
+        )?;
         self.registers.pc.write(addr);
         self.registers.sp -= 2;
-        self.delay += 6;
         log::trace!("call_cond_u16: calling subroutine 0x{addr:x?}");
         Ok(())
     }
 
-    pub fn add_a_u8(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn add_a_u8(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let a = self.registers.af.high.read();
         let (val, c) = a.overflowing_add(arg);
         self.registers.af.high.write(val);
@@ -573,12 +563,11 @@ impl Cpu {
         self.registers.af.low.c = c;
 
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn sub_a_u8(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn sub_a_u8(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let a = self.registers.af.high.read();
         let (val, c) = a.overflowing_sub(arg);
         self.registers.af.high.write(val);
@@ -592,23 +581,21 @@ impl Cpu {
         self.registers.af.low.c = c;
 
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn ld_r8_ptr_hl(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn ld_r8_ptr_hl(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00111000) >> 3;
-        let val = self.mmu.read(self.registers.hl.read())?;
+        let val = self.bus_read_ptr(self.registers.hl.read())?;
         let target = self.registers.get_r8(reg);
         target.write(val);
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn xor_a_ptr_hl(&mut self) -> anyhow::Result<()> {
+    pub fn xor_a_ptr_hl(&mut self) -> Result<(), EmuError> {
         let a = self.registers.af.high.read();
-        let target = self.mmu.read(self.registers.hl.read())?;
+        let target = self.bus_read_ptr(self.registers.hl.read())?;
         let val = a ^ target;
         self.registers.af.high.write(val);
 
@@ -618,11 +605,10 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn srl_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn srl_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let target = self.registers.get_r8(reg);
         let b0 = target.read() & 0b1;
@@ -635,12 +621,11 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 2;
-        self.delay += 2;
 
         Ok(())
     }
 
-    pub fn rr_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn rr_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let target = self.registers.get_r8(reg).read();
         let b0 = target & 0b1;
@@ -657,12 +642,11 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 2;
-        self.delay += 2;
 
         Ok(())
     }
 
-    pub fn rra(&mut self) -> anyhow::Result<()> {
+    pub fn rra(&mut self) -> Result<(), EmuError> {
         let target = self.registers.af.high.read();
         let b0 = target & 0b1;
         let val = if self.registers.af.low.c {
@@ -678,12 +662,11 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 1;
-        self.delay += 1;
 
         Ok(())
     }
-    pub fn xor_a_u8(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn xor_a_u8(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let a = self.registers.af.high.read();
         let val = a ^ arg;
         self.registers.af.high.write(val);
@@ -694,11 +677,10 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
-    pub fn adc_a_u8(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn adc_a_u8(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let a = self.registers.af.high.read();
         let c = if self.registers.af.low.c { 1 } else { 0 };
 
@@ -720,30 +702,29 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn ret_cond(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn ret_cond(&mut self, opcode: u8) -> Result<(), EmuError> {
         let code = (opcode & 0b00011000) >> 3;
         let cond = self.registers.get_cond(code);
+        self.tick()?;
         if !cond {
             self.registers.pc += 1;
-            self.delay += 2;
             return Ok(());
         }
 
-        let low = self.mmu.read(self.registers.sp.read())?;
-        let high = self.mmu.read(self.registers.sp.read() + 1)?;
+        let low = self.bus_read(self.registers.sp.read())?;
+        let high = self.bus_read(self.registers.sp.read().wrapping_add(1))?;
         self.registers.pc.high.write(high);
         self.registers.pc.low.write(low);
         self.registers.sp += 2;
-        self.delay += 5;
+        self.tick()?;
         Ok(())
     }
 
-    pub fn or_a_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let target = self.mmu.read(self.registers.hl.read())?;
+    pub fn or_a_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let target = self.bus_read_ptr(self.registers.hl.read())?;
         let a = self.registers.af.high.read();
         let val = target | a;
         self.registers.af.low.z = val == 0;
@@ -752,14 +733,13 @@ impl Cpu {
         self.registers.af.low.n = false;
         self.registers.af.high.write(val);
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn dec_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let target = self.mmu.read(self.registers.hl.read())?;
+    pub fn dec_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let target = self.bus_read_ptr(self.registers.hl.read())?;
         let (val, _) = target.overflowing_sub(1);
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
 
         self.registers.af.low.z = val == 0;
         self.registers.af.low.h = {
@@ -768,14 +748,13 @@ impl Cpu {
         };
         self.registers.af.low.n = true;
         self.registers.pc += 1;
-        self.delay += 3;
 
         Ok(())
     }
-    pub fn inc_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let target = self.mmu.read(self.registers.hl.read())?;
+    pub fn inc_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let target = self.bus_read_ptr(self.registers.hl.read())?;
         let (val, _) = target.overflowing_add(1);
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
 
         self.registers.af.low.z = val == 0;
         self.registers.af.low.h = {
@@ -784,11 +763,10 @@ impl Cpu {
         };
         self.registers.af.low.n = false;
         self.registers.pc += 1;
-        self.delay += 3;
 
         Ok(())
     }
-    pub fn add_hl_rr(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn add_hl_rr(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = (opcode & 0b00110000) >> 4;
         let target = self.registers.get_r16_ss(reg).read();
         let hl = self.registers.hl.read();
@@ -804,17 +782,16 @@ impl Cpu {
         self.registers.hl.write(val);
 
         self.registers.pc += 1;
-        self.delay += 2;
+        self.tick()?;
         Ok(())
     }
 
-    pub fn jp_hl(&mut self) -> anyhow::Result<()> {
+    pub fn jp_hl(&mut self) -> Result<(), EmuError> {
         self.registers.pc.write(self.registers.hl.read());
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn swap_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn swap_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let target = self.registers.get_r8(reg);
         let r = target.read();
@@ -829,10 +806,9 @@ impl Cpu {
         self.registers.af.low.c = false;
 
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
-    pub fn and_a_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn and_a_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let target = self.registers.get_r8(reg).read();
         let a = self.registers.af.high.read();
@@ -844,28 +820,30 @@ impl Cpu {
         self.registers.af.low.h = true;
         self.registers.af.low.c = false;
         self.registers.pc += 1;
-        self.delay += 1;
 
         Ok(())
     }
 
-    pub fn rst(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn rst(&mut self, opcode: u8) -> Result<(), EmuError> {
         let n = (opcode & 0b00111000) >> 3;
         let a = (n * 8) as u16;
         self.registers.pc += 1;
-        self.mmu
-            .write(self.registers.sp.read() - 1, self.registers.pc.high.read())?;
-        self.mmu
-            .write(self.registers.sp.read() - 2, self.registers.pc.low.read())?;
+        self.tick()?;
+        self.bus_write(
+            self.registers.sp.read().wrapping_sub(1),
+            self.registers.pc.high.read(),
+        )?;
+        self.bus_write(
+            self.registers.sp.read().wrapping_sub(2),
+            self.registers.pc.low.read(),
+        )?;
         self.registers.sp -= 2;
         self.registers.pc.write(a);
 
-        self.delay += 4;
-
         Ok(())
     }
 
-    pub fn add_a_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn add_a_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let arg = self.registers.get_r8(reg).read();
         let a = self.registers.af.high.read();
@@ -881,12 +859,11 @@ impl Cpu {
         self.registers.af.low.c = c;
 
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn or_a_u8(&mut self) -> anyhow::Result<()> {
-        let target = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn or_a_u8(&mut self) -> Result<(), EmuError> {
+        let target = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let a = self.registers.af.high.read();
         let val = target | a;
         self.registers.af.low.z = val == 0;
@@ -895,74 +872,69 @@ impl Cpu {
         self.registers.af.low.n = false;
         self.registers.af.high.write(val);
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn set_b_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn set_b_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let bit = (opcode & 0b00111000) >> 3;
         let target = self.registers.get_r8(reg);
         let val = target.read() | (1 << bit);
         target.write(val);
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn set_b_ptr_hl(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn set_b_ptr_hl(&mut self, opcode: u8) -> Result<(), EmuError> {
         let bit = (opcode & 0b00111000) >> 3;
-        let target = self.mmu.read(self.registers.hl.read())?;
+        let target = self.bus_read_ptr(self.registers.hl.read())?;
         let val = target | (1 << bit);
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
         self.registers.pc += 2;
-        self.delay += 4;
         Ok(())
     }
-    pub fn ld_ptr_u16_sp(&mut self) -> anyhow::Result<()> {
-        let low = self.mmu.read(self.registers.pc.read() + 1)?;
-        let high = self.mmu.read(self.registers.pc.read() + 2)?;
+    pub fn ld_ptr_u16_sp(&mut self) -> Result<(), EmuError> {
+        let low = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
+        let high = self.bus_read(self.registers.pc.read().wrapping_add(2))?;
         let addr = ((high as u16) << 8) | (low as u16);
-        self.mmu.write(addr, self.registers.sp.low.read())?;
-        self.mmu.write(addr + 1, self.registers.sp.high.read())?;
+        self.bus_write(addr, self.registers.sp.low.read())?;
+        self.bus_write(addr.wrapping_add(1), self.registers.sp.high.read())?;
         self.registers.pc += 3;
-        self.delay += 5;
         Ok(())
     }
-    pub fn ld_sp_hl(&mut self) -> anyhow::Result<()> {
+    pub fn ld_sp_hl(&mut self) -> Result<(), EmuError> {
         self.registers.sp.write(self.registers.hl.read());
         self.registers.pc += 1;
-        self.delay += 2;
+        self.tick()?;
         Ok(())
     }
-    pub fn jp_cond_u16(&mut self, opcode: u8) -> anyhow::Result<()> {
-        let low = self.mmu.read(self.registers.pc.read() + 1)?;
-        let high = self.mmu.read(self.registers.pc.read() + 2)?;
+    pub fn jp_cond_u16(&mut self, opcode: u8) -> Result<(), EmuError> {
+        let low = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
+        let high = self.bus_read(self.registers.pc.read().wrapping_add(2))?;
         let addr = ((high as u16) << 8) | (low as u16);
         let code = (opcode & 0b00011000) >> 3;
         let cond = self.registers.get_cond(code);
         if cond {
             self.registers.pc.write(addr);
-            self.delay += 4;
+            self.tick()?;
         } else {
             self.registers.pc += 3;
-            self.delay += 3;
         }
 
         Ok(())
     }
-    pub fn reti(&mut self) -> anyhow::Result<()> {
-        let low = self.mmu.read(self.registers.sp.read())?;
-        let high = self.mmu.read(self.registers.sp.read() + 1)?;
+    pub fn reti(&mut self) -> Result<(), EmuError> {
+        let low = self.bus_read(self.registers.sp.read())?;
+        let high = self.bus_read(self.registers.sp.read().wrapping_add(1))?;
         self.registers.pc.high.write(high);
         self.registers.pc.low.write(low);
         self.registers.sp += 2;
-        self.delay += 4;
+        self.tick()?;
         self.ime = true;
         Ok(())
     }
-    pub fn add_sp_i8(&mut self) -> anyhow::Result<()> {
-        let offset: u8 = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn add_sp_i8(&mut self) -> Result<(), EmuError> {
+        let offset: u8 = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let offset = offset as i8 as i16;
         let sp = self.registers.sp.read();
         let (val, _) = sp.overflowing_add_signed(offset);
@@ -977,13 +949,14 @@ impl Cpu {
             let (x, _) = (sp & 0xff).overflowing_add_signed(offset & 0xff);
             (x & 0x100) > 0
         };
+        self.tick()?;
+        self.tick()?;
         self.registers.sp.write(val);
         self.registers.pc += 2;
-        self.delay += 4;
         Ok(())
     }
-    pub fn ld_hl_sp_i8(&mut self) -> anyhow::Result<()> {
-        let offset: u8 = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn ld_hl_sp_i8(&mut self) -> Result<(), EmuError> {
+        let offset: u8 = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let offset = offset as i8 as i16;
         let sp = self.registers.sp.read();
         let (val, _) = sp.overflowing_add_signed(offset);
@@ -998,13 +971,13 @@ impl Cpu {
             let (x, _) = (sp & 0xff).overflowing_add_signed(offset & 0xff);
             (x & 0x100) > 0
         };
+        self.tick()?;
         self.registers.hl.write(val);
         self.registers.pc += 2;
-        self.delay += 3;
         Ok(())
     }
 
-    pub fn cp_a_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn cp_a_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let arg = self.registers.get_r8(reg).read();
         let a = self.registers.af.high.read();
@@ -1018,12 +991,11 @@ impl Cpu {
         };
 
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn sbc_a_u8(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.pc.read() + 1)?;
+    pub fn sbc_a_u8(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read(self.registers.pc.read().wrapping_add(1))?;
         let a = self.registers.af.high.read();
         let c = if self.registers.af.low.c { 1 } else { 0 };
 
@@ -1045,26 +1017,23 @@ impl Cpu {
         self.registers.af.low.n = true;
 
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
-    pub fn scf(&mut self) -> anyhow::Result<()> {
+    pub fn scf(&mut self) -> Result<(), EmuError> {
         self.registers.af.low.n = false;
         self.registers.af.low.h = false;
         self.registers.af.low.c = true;
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
-    pub fn ccf(&mut self) -> anyhow::Result<()> {
+    pub fn ccf(&mut self) -> Result<(), EmuError> {
         self.registers.af.low.n = false;
         self.registers.af.low.h = false;
         self.registers.af.low.c = !self.registers.af.low.c;
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
-    pub fn adc_a_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn adc_a_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let arg = self.registers.get_r8(reg).read();
         let a = self.registers.af.high.read();
@@ -1088,11 +1057,10 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 
-    pub fn sbc_a_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn sbc_a_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let arg = self.registers.get_r8(reg).read();
         let a = self.registers.af.high.read();
@@ -1116,10 +1084,9 @@ impl Cpu {
         self.registers.af.low.n = true;
 
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
-    pub fn rlca(&mut self) -> anyhow::Result<()> {
+    pub fn rlca(&mut self) -> Result<(), EmuError> {
         let val = self.registers.af.high.read();
         let c = (val & 0b10000000) > 0;
         let val = if c { (val << 1) | 1 } else { val << 1 };
@@ -1129,10 +1096,9 @@ impl Cpu {
         self.registers.af.low.n = false;
         self.registers.af.high.write(val);
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
-    pub fn rrca(&mut self) -> anyhow::Result<()> {
+    pub fn rrca(&mut self) -> Result<(), EmuError> {
         let val = self.registers.af.high.read();
         let c = (val & 0b00000001) > 0;
         let val = if c { (val >> 1) | 0b10000000 } else { val >> 1 };
@@ -1142,10 +1108,9 @@ impl Cpu {
         self.registers.af.low.n = false;
         self.registers.af.high.write(val);
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
-    pub fn rlc_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn rlc_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let code = opcode & 0b00000111;
         let target = self.registers.get_r8(code);
         let val = target.read();
@@ -1157,10 +1122,9 @@ impl Cpu {
         self.registers.af.low.h = false;
         self.registers.af.low.n = false;
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
-    pub fn rrc_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn rrc_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let code = opcode & 0b00000111;
         let target = self.registers.get_r8(code);
         let val = target.read();
@@ -1172,11 +1136,10 @@ impl Cpu {
         self.registers.af.low.h = false;
         self.registers.af.low.n = false;
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn sla_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn sla_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let code = opcode & 0b00000111;
         let target = self.registers.get_r8(code);
         let val = target.read();
@@ -1188,11 +1151,10 @@ impl Cpu {
         self.registers.af.low.h = false;
         self.registers.af.low.n = false;
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn sra_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn sra_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let code = opcode & 0b00000111;
         let target = self.registers.get_r8(code);
         let val = target.read();
@@ -1209,32 +1171,29 @@ impl Cpu {
         self.registers.af.low.h = false;
         self.registers.af.low.n = false;
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn res_b_r8(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn res_b_r8(&mut self, opcode: u8) -> Result<(), EmuError> {
         let reg = opcode & 0b00000111;
         let bit = (opcode & 0b00111000) >> 3;
         let target = self.registers.get_r8(reg);
         let val = target.read() & !(1 << bit);
         target.write(val);
         self.registers.pc += 2;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn ld_a_ptr_ff00_c(&mut self) -> anyhow::Result<()> {
+    pub fn ld_a_ptr_ff00_c(&mut self) -> Result<(), EmuError> {
         let c = self.registers.bc.low.read() as u16;
         let addr = 0xff00 + c;
-        let val = self.mmu.read(addr)?;
+        let val = self.bus_read(addr)?;
         self.registers.af.high.write(val);
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
-    pub fn adc_a_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.hl.read())?;
+    pub fn adc_a_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read_ptr(self.registers.hl.read())?;
         let a = self.registers.af.high.read();
         let c = if self.registers.af.low.c { 1 } else { 0 };
 
@@ -1256,12 +1215,11 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
 
-    pub fn sub_a_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.hl.read())?;
+    pub fn sub_a_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read_ptr(self.registers.hl.read())?;
         let a = self.registers.af.high.read();
         let (val, c) = a.overflowing_sub(arg);
         self.registers.af.high.write(val);
@@ -1275,11 +1233,10 @@ impl Cpu {
         self.registers.af.low.c = c;
 
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
-    pub fn sbc_a_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.hl.read())?;
+    pub fn sbc_a_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read_ptr(self.registers.hl.read())?;
         let a = self.registers.af.high.read();
         let c = if self.registers.af.low.c { 1 } else { 0 };
 
@@ -1301,11 +1258,10 @@ impl Cpu {
         self.registers.af.low.n = true;
 
         self.registers.pc += 1;
-        self.delay += 2;
         Ok(())
     }
-    pub fn and_a_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let arg = self.mmu.read(self.registers.hl.read())?;
+    pub fn and_a_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let arg = self.bus_read_ptr(self.registers.hl.read())?;
         let a = self.registers.af.high.read();
         let val = a & arg;
         self.registers.af.high.write(val);
@@ -1314,41 +1270,38 @@ impl Cpu {
         self.registers.af.low.h = true;
         self.registers.af.low.c = false;
         self.registers.pc += 1;
-        self.delay += 2;
 
         Ok(())
     }
-    pub fn rlc_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let val = self.mmu.read(self.registers.hl.read())?;
+    pub fn rlc_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let val = self.bus_read_ptr(self.registers.hl.read())?;
         let c = (val & 0b10000000) > 0;
         let val = if c { (val << 1) | 1 } else { val << 1 };
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
 
         self.registers.af.low.c = c;
         self.registers.af.low.z = val == 0;
         self.registers.af.low.h = false;
         self.registers.af.low.n = false;
         self.registers.pc += 2;
-        self.delay += 4;
         Ok(())
     }
-    pub fn rrc_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let val = self.mmu.read(self.registers.hl.read())?;
+    pub fn rrc_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let val = self.bus_read_ptr(self.registers.hl.read())?;
         let c = (val & 0b00000001) > 0;
 
         let val = if c { (val >> 1) | 0b10000000 } else { val >> 1 };
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
         self.registers.af.low.c = c;
         self.registers.af.low.z = val == 0;
         self.registers.af.low.h = false;
         self.registers.af.low.n = false;
         self.registers.pc += 2;
-        self.delay += 4;
         Ok(())
     }
 
-    pub fn rl_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let val = self.mmu.read(self.registers.hl.read())?;
+    pub fn rl_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let val = self.bus_read_ptr(self.registers.hl.read())?;
 
         let b7 = (val & 0b10000000) > 0;
         let val = {
@@ -1359,7 +1312,7 @@ impl Cpu {
                 val
             }
         };
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
 
         self.registers.af.low.z = val == 0;
         self.registers.af.low.n = false;
@@ -1367,18 +1320,17 @@ impl Cpu {
         self.registers.af.low.c = b7;
 
         self.registers.pc += 2;
-        self.delay += 4;
         Ok(())
     }
-    pub fn rr_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let target = self.mmu.read(self.registers.hl.read())?;
+    pub fn rr_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let target = self.bus_read_ptr(self.registers.hl.read())?;
         let b0 = target & 0b1;
         let val = if self.registers.af.low.c {
             (target >> 1) | 0b10000000
         } else {
             target >> 1
         };
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
 
         self.registers.af.low.z = val == 0;
         self.registers.af.low.c = b0 > 0;
@@ -1386,26 +1338,24 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 2;
-        self.delay += 4;
 
         Ok(())
     }
-    pub fn sla_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let val = self.mmu.read(self.registers.hl.read())?;
+    pub fn sla_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let val = self.bus_read_ptr(self.registers.hl.read())?;
         let c = (val & 0b10000000) > 0;
         let val = val << 1;
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
         self.registers.af.low.c = c;
         self.registers.af.low.z = val == 0;
         self.registers.af.low.h = false;
         self.registers.af.low.n = false;
         self.registers.pc += 2;
-        self.delay += 4;
         Ok(())
     }
 
-    pub fn sra_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let val = self.mmu.read(self.registers.hl.read())?;
+    pub fn sra_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let val = self.bus_read_ptr(self.registers.hl.read())?;
         let c = (val & 0b00000001) > 0;
         let b7 = (val & 0b10000000) > 0;
         let val = if b7 {
@@ -1413,37 +1363,35 @@ impl Cpu {
         } else {
             val >> 1
         };
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
         self.registers.af.low.c = c;
         self.registers.af.low.z = val == 0;
         self.registers.af.low.h = false;
         self.registers.af.low.n = false;
         self.registers.pc += 2;
-        self.delay += 4;
         Ok(())
     }
-    pub fn swap_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let r = self.mmu.read(self.registers.hl.read())?;
+    pub fn swap_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let r = self.bus_read_ptr(self.registers.hl.read())?;
         let high = (r & 0xf0) >> 4;
         let low = r & 0x0f;
         let val = (low << 4) | high;
 
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
         self.registers.af.low.z = val == 0;
         self.registers.af.low.n = false;
         self.registers.af.low.h = false;
         self.registers.af.low.c = false;
 
         self.registers.pc += 2;
-        self.delay += 4;
         Ok(())
     }
 
-    pub fn srl_ptr_hl(&mut self) -> anyhow::Result<()> {
-        let r = self.mmu.read(self.registers.hl.read())?;
+    pub fn srl_ptr_hl(&mut self) -> Result<(), EmuError> {
+        let r = self.bus_read_ptr(self.registers.hl.read())?;
         let b0 = r & 0b1;
         let val = r >> 1;
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
 
         self.registers.af.low.z = val == 0;
         self.registers.af.low.c = b0 > 0;
@@ -1451,33 +1399,30 @@ impl Cpu {
         self.registers.af.low.n = false;
 
         self.registers.pc += 2;
-        self.delay += 4;
 
         Ok(())
     }
 
-    pub fn bit_b_ptr_hl(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn bit_b_ptr_hl(&mut self, opcode: u8) -> Result<(), EmuError> {
         let bit = (opcode & 0b00111000) >> 3;
-        let target = self.mmu.read(self.registers.hl.read())?;
+        let target = self.bus_read_ptr(self.registers.hl.read())?;
         self.registers.af.low.z = (target & (1 << bit)) == 0;
         self.registers.af.low.h = true;
         self.registers.af.low.n = false;
-        self.delay += 3;
         self.registers.pc += 2;
         Ok(())
     }
 
-    pub fn res_b_ptr_hl(&mut self, opcode: u8) -> anyhow::Result<()> {
+    pub fn res_b_ptr_hl(&mut self, opcode: u8) -> Result<(), EmuError> {
         let bit = (opcode & 0b00111000) >> 3;
-        let target = self.mmu.read(self.registers.hl.read())?;
+        let target = self.bus_read_ptr(self.registers.hl.read())?;
         let val = target & !(1 << bit);
-        self.mmu.write(self.registers.hl.read(), val)?;
+        self.bus_write(self.registers.hl.read(), val)?;
         self.registers.pc += 2;
-        self.delay += 4;
         Ok(())
     }
 
-    pub fn daa(&mut self) -> anyhow::Result<()> {
+    pub fn daa(&mut self) -> Result<(), EmuError> {
         // adapted from https://gbdev.gg8.se/wiki/articles/DAA
         if self.registers.af.low.n {
             if self.registers.af.low.c {
@@ -1499,16 +1444,45 @@ impl Cpu {
         self.registers.af.low.h = false;
 
         self.registers.pc += 1;
-        self.delay += 1;
 
         Ok(())
     }
 
-    pub fn halt(&mut self) -> anyhow::Result<()> {
+    pub fn stop(&mut self) -> Result<(), EmuError> {
+        log::debug!("STOP");
+        if self.speed_switch() {
+            self.registers.pc += 2;
+            return Ok(());
+        }
+        if self.mmu.joypad_lines() != 0b1111 {
+            // a held button would wake us up straight away, it ends up acting as a one byte NOP
+            self.registers.pc += 1;
+            return Ok(());
+        }
+        self.mmu.timer.write_div();
+        self.stopped = true;
+        self.registers.pc += 2;
+        Ok(())
+    }
+
+    pub fn illegal(&mut self, opcode: u8) -> Result<(), EmuError> {
+        log::warn!(
+            "illegal opcode 0x{opcode:02x} at 0x{:04x}, locking up",
+            self.registers.pc.read()
+        );
+        self.locked = Some(opcode);
+        Ok(())
+    }
+
+    pub fn halt(&mut self) -> Result<(), EmuError> {
         log::debug!("halt!");
-        self.halted = true;
+        if !self.ime && self.pending_interrupts() > 0 {
+            log::debug!("halt: halt bug");
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
         self.registers.pc += 1;
-        self.delay += 1;
         Ok(())
     }
 }
@@ -0,0 +1,63 @@
+use std::fmt;
+
+/// Errors raised while emulating, so embedders can tell a bad game from an emulator bug
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum EmuError {
+    #[error("{mapper}: bad RAM bank 0x{bank:02x} at 0x{addr:04x}{}", Pc(*pc))]
+    BadRamBank {
+        mapper: &'static str,
+        pc: Option<u16>,
+        addr: u16,
+        bank: u8,
+    },
+    #[error("{device}: {} out of bounds at 0x{addr:04x}{}", access(*write), Pc(*pc))]
+    OutOfBounds {
+        device: &'static str,
+        pc: Option<u16>,
+        addr: u16,
+        write: bool,
+    },
+    #[error("Apu: invalid register {} at 0x{addr:04x}{}", access(*write), Pc(*pc))]
+    InvalidApuRegister {
+        pc: Option<u16>,
+        addr: u16,
+        write: bool,
+    },
+}
+
+impl EmuError {
+    /// Fills in the PC for errors raised below the CPU, which has no idea what it is
+    pub fn at_pc(mut self, at: u16) -> Self {
+        match &mut self {
+            EmuError::BadRamBank { pc, .. }
+            | EmuError::OutOfBounds { pc, .. }
+            | EmuError::InvalidApuRegister { pc, .. } => {
+                pc.get_or_insert(at);
+            }
+        }
+        self
+    }
+
+    pub fn pc(&self) -> Option<u16> {
+        match self {
+            EmuError::BadRamBank { pc, .. }
+            | EmuError::OutOfBounds { pc, .. }
+            | EmuError::InvalidApuRegister { pc, .. } => *pc,
+        }
+    }
+}
+
+fn access(write: bool) -> &'static str {
+    if write { "write" } else { "read" }
+}
+
+struct Pc(Option<u16>);
+
+impl fmt::Display for Pc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(pc) => write!(f, " (PC 0x{pc:04x})"),
+            None => Ok(()),
+        }
+    }
+}
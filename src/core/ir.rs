@@ -0,0 +1,60 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// One end of an infrared link, `tx` is our LED and `rx` is whatever LED we're pointed at
+#[derive(Debug, Clone, Default)]
+pub struct IrLink {
+    tx: Arc<AtomicBool>,
+    rx: Arc<AtomicBool>,
+}
+
+impl IrLink {
+    /// Two ends pointed at each other, for linking two emulator instances in the same process.
+    /// Run them with `Cpu::frame_linked` so neither gets ahead of the other
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use gbrs::core::{cpu::Cpu, ir::IrLink};
+    ///
+    /// let rom = std::fs::read("game.gb")?;
+    /// let mut a = Cpu::new(rom.clone(), 48000)?;
+    /// let mut b = Cpu::new(rom, 48000)?;
+    /// let (link_a, link_b) = IrLink::pair();
+    /// a.mmu.cartridge.mbc.connect_ir(link_a);
+    /// b.mmu.cartridge.mbc.connect_ir(link_b);
+    /// loop {
+    ///     a.frame_linked(&mut b)?;
+    /// }
+    /// # }
+    /// ```
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(AtomicBool::new(false));
+        let b = Arc::new(AtomicBool::new(false));
+        (
+            Self {
+                tx: a.clone(),
+                rx: b.clone(),
+            },
+            Self { tx: b, rx: a },
+        )
+    }
+
+    /// A link that sees its own LED, handy for testing IR code with a single instance
+    pub fn loopback() -> Self {
+        let a = Arc::new(AtomicBool::new(false));
+        Self {
+            tx: a.clone(),
+            rx: a,
+        }
+    }
+
+    pub fn set_led(&self, on: bool) {
+        self.tx.store(on, Ordering::Relaxed);
+    }
+
+    pub fn receiving(&self) -> bool {
+        self.rx.load(Ordering::Relaxed)
+    }
+}
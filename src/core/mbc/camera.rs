@@ -0,0 +1,304 @@
+use anyhow::anyhow;
+
+use crate::core::{
+    error::EmuError,
+    mbc::{Mbc, load_ram, save_ram},
+};
+
+/// Size of the image the sensor hands to the cartridge, the M64282FP is 128x128 but the
+/// camera only uses the middle 112 rows
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+/// Number of photo slots in SRAM, 2 per bank from bank 1 onwards
+pub const PHOTO_COUNT: usize = 30;
+
+/// Where the captured image is written in RAM bank 0
+const CAPTURE_BASE: usize = 0x0100;
+const PHOTO_SIZE: usize = SENSOR_WIDTH * SENSOR_HEIGHT / 4;
+
+/// Decodes a PNG or JPEG into a grayscale sensor frame, scaled to fill the sensor
+pub fn decode_frame(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let image = image::load_from_memory(bytes)?;
+    let image = image
+        .resize_to_fill(
+            SENSOR_WIDTH as u32,
+            SENSOR_HEIGHT as u32,
+            image::imageops::FilterType::Triangle,
+        )
+        .into_luma8();
+    Ok(image.into_raw())
+}
+
+/// Encodes 2bpp pixels (0 is white, 3 is black) as a grayscale PNG
+pub fn encode_png(pixels: &[u8], width: usize, height: usize) -> anyhow::Result<Vec<u8>> {
+    let gray: Vec<u8> = pixels.iter().map(|x| 0xff - x * 0x55).collect();
+    let image = image::GrayImage::from_raw(width as u32, height as u32, gray).ok_or(anyhow!(
+        "PocketCamera: image doesn't match {width}x{height}"
+    ))?;
+    let mut out = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut out, image::ImageFormat::Png)?;
+    Ok(out.into_inner())
+}
+
+/// Encodes photos as a single PNG, laid out in rows of 6 like the camera's album
+pub fn encode_photo_sheet(photos: &[Vec<u8>]) -> anyhow::Result<Vec<u8>> {
+    const COLUMNS: usize = 6;
+    let rows = photos.len().div_ceil(COLUMNS);
+    let width = COLUMNS * SENSOR_WIDTH;
+    let height = rows * SENSOR_HEIGHT;
+    let mut sheet = vec![0; width * height];
+    for (i, photo) in photos.iter().enumerate() {
+        let left = (i % COLUMNS) * SENSOR_WIDTH;
+        let top = (i / COLUMNS) * SENSOR_HEIGHT;
+        for (y, row) in photo.chunks(SENSOR_WIDTH).enumerate() {
+            let start = (top + y) * width + left;
+            sheet[start..start + SENSOR_WIDTH].copy_from_slice(row);
+        }
+    }
+    encode_png(&sheet, width, height)
+}
+
+/// Converts 16x14 tiles of 2bpp tile data back into pixels
+fn decode_tiles(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0; SENSOR_WIDTH * SENSOR_HEIGHT];
+    for (y, row) in out.chunks_mut(SENSOR_WIDTH).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let idx = ((y / 8) * 16 + x / 8) * 16 + (y % 8) * 2;
+            let shift = 7 - (x % 8);
+            let b1 = (data[idx] >> shift) & 1;
+            let b2 = (data[idx + 1] >> shift) & 1;
+            *pixel = (b2 << 1) | b1;
+        }
+    }
+    out
+}
+
+#[derive(Debug)]
+pub struct PocketCamera {
+    rom: Vec<Vec<u8>>,
+    ram: Vec<Vec<u8>>,
+    rom_banks: usize,
+    ram_banks: usize,
+    rom_bank: u8,
+    ram_bank: u8,
+    ram_enable: bool,
+    registers: [u8; 0x36],
+    busy: usize,
+    frames: Vec<Vec<u8>>,
+    frame: usize,
+}
+
+impl PocketCamera {
+    pub fn new(rom: Vec<u8>, rom_banks: usize, ram_banks: usize) -> Self {
+        let rom = rom.chunks(0x4000).map(|x| x.to_vec()).collect();
+        let ram = vec![vec![0; 0x2000]; ram_banks];
+        Self {
+            rom,
+            ram,
+            rom_banks,
+            ram_banks,
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enable: false,
+            registers: [0; 0x36],
+            busy: 0,
+            frames: Vec::new(),
+            frame: 0,
+        }
+    }
+
+    fn registers_mapped(&self) -> bool {
+        (self.ram_bank & 0b0001_0000) > 0
+    }
+
+    fn exposure(&self) -> usize {
+        ((self.registers[2] as usize) << 8) | self.registers[3] as usize
+    }
+
+    /// Reads a sensor pixel, clamping to the edges so the edge filter has something to work with
+    fn sensor(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, SENSOR_WIDTH as isize - 1) as usize;
+        let y = y.clamp(0, SENSOR_HEIGHT as isize - 1) as usize;
+        match self.frames.get(self.frame) {
+            Some(frame) => frame[y * SENSOR_WIDTH + x] as f32,
+            None => 0x80 as f32, // no image loaded, show a flat gray
+        }
+    }
+
+    fn capture(&mut self) {
+        let exposure = self.exposure() as f32 / 0x1000 as f32;
+        // N (A001 bit 7) enables edge extraction, VH (A001 bits 5-6) picks the direction
+        let n = (self.registers[1] & 0b1000_0000) > 0;
+        let vh = if n {
+            (self.registers[1] & 0b0110_0000) >> 5
+        } else {
+            0
+        };
+        let edge_ratio = match (self.registers[4] & 0b0111_0000) >> 4 {
+            0 => 0.5,
+            1 => 0.75,
+            2 => 1.0,
+            3 => 1.25,
+            4 => 2.0,
+            5 => 3.0,
+            6 => 4.0,
+            _ => 5.0,
+        };
+        let invert = (self.registers[4] & 0b0000_1000) > 0;
+
+        let mut tiles = vec![0; PHOTO_SIZE];
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let (sx, sy) = (x as isize, y as isize);
+                let centre = self.sensor(sx, sy);
+                let horizontal = 2.0 * centre - self.sensor(sx - 1, sy) - self.sensor(sx + 1, sy);
+                let vertical = 2.0 * centre - self.sensor(sx, sy - 1) - self.sensor(sx, sy + 1);
+                let edge = match vh {
+                    0b01 => horizontal,
+                    0b10 => vertical,
+                    0b11 => horizontal + vertical,
+                    _ => 0.0,
+                };
+                let mut value = ((centre + edge * edge_ratio) * exposure).clamp(0.0, 255.0) as u8;
+                if invert {
+                    value = 0xff - value;
+                }
+
+                // the 4x4 matrix holds 3 thresholds per pixel, darker than a threshold is a
+                // darker shade
+                let matrix = 6 + ((y % 4) * 4 + (x % 4)) * 3;
+                let color = if value < self.registers[matrix] {
+                    3
+                } else if value < self.registers[matrix + 1] {
+                    2
+                } else if value < self.registers[matrix + 2] {
+                    1
+                } else {
+                    0
+                };
+
+                let idx = ((y / 8) * 16 + x / 8) * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                tiles[idx] |= (color & 1) << bit;
+                tiles[idx + 1] |= ((color >> 1) & 1) << bit;
+            }
+        }
+        self.ram[0][CAPTURE_BASE..CAPTURE_BASE + PHOTO_SIZE].copy_from_slice(&tiles);
+        if !self.frames.is_empty() {
+            // step through image sequences one capture at a time
+            self.frame = (self.frame + 1) % self.frames.len();
+        }
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
+        let addr = addr as usize;
+        let rom_bank = self.rom_bank as usize;
+        match addr {
+            0x0000..=0x3fff => Ok(self.rom[0][addr]),
+            0x4000..=0x7fff => Ok(self.rom[rom_bank % self.rom_banks][addr - 0x4000]),
+            0xa000..=0xbfff => {
+                if self.registers_mapped() {
+                    // only A000 can be read back, and only its status bits
+                    match addr & 0x7f {
+                        0x00 => Ok((self.registers[0] & 0b0000_0110) | (self.busy > 0) as u8),
+                        _ => Ok(0x00),
+                    }
+                } else if self.busy > 0 {
+                    // RAM is disconnected while the sensor is being read
+                    Ok(0x00)
+                } else {
+                    let ram_bank = (self.ram_bank & 0x0f) as usize;
+                    Ok(self.ram[ram_bank % self.ram_banks][addr - 0xa000])
+                }
+            }
+            _ => Err(EmuError::OutOfBounds {
+                device: "PocketCamera",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable = (val & 0x0f) == 0x0a;
+                Ok(())
+            }
+            0x2000..=0x3fff => {
+                self.rom_bank = val & 0b0011_1111;
+                Ok(())
+            }
+            0x4000..=0x5fff => {
+                self.ram_bank = val & 0b0001_1111;
+                Ok(())
+            }
+            0x6000..=0x7fff => Ok(()),
+            0xa000..=0xbfff => {
+                if self.registers_mapped() {
+                    let reg = addr & 0x7f;
+                    if reg == 0x00 {
+                        self.registers[0] = val & 0b0000_0111;
+                        if (val & 0b0000_0001) > 0 && self.busy == 0 {
+                            // capture time in M-cycles, N being cleared adds a bit of time
+                            let n = (self.registers[1] & 0b1000_0000) > 0;
+                            self.busy = 32446 + if n { 0 } else { 512 } + 16 * self.exposure();
+                            log::debug!("PocketCamera: capture started, {} cycles", self.busy);
+                        }
+                    } else if reg < self.registers.len() {
+                        self.registers[reg] = val;
+                    }
+                } else if self.ram_enable && self.busy == 0 {
+                    let ram_bank = (self.ram_bank & 0x0f) as usize;
+                    self.ram[ram_bank % self.ram_banks][addr - 0xa000] = val;
+                }
+                Ok(())
+            }
+            _ => Err(EmuError::OutOfBounds {
+                device: "PocketCamera",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.busy == 0 {
+            return;
+        }
+        self.busy -= 1;
+        if self.busy == 0 {
+            self.capture();
+            self.registers[0] &= !0b0000_0001;
+            log::debug!("PocketCamera: capture finished");
+        }
+    }
+
+    fn set_camera_frames(&mut self, frames: Vec<Vec<u8>>) {
+        self.frames = frames;
+        self.frame = 0;
+    }
+
+    fn photos(&self) -> Vec<Vec<u8>> {
+        (0..PHOTO_COUNT)
+            .filter_map(|i| {
+                let bank = self.ram.get(1 + i / 2)?;
+                let base = (i % 2) * 0x1000;
+                Some(decode_tiles(&bank[base..base + PHOTO_SIZE]))
+            })
+            .collect()
+    }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        Some(save_ram(&self.ram))
+    }
+
+    fn load_save(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        load_ram("PocketCamera", &mut self.ram, data)
+    }
+}
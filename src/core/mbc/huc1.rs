@@ -0,0 +1,108 @@
+use crate::core::{
+    error::EmuError,
+    ir::IrLink,
+    mbc::{Mbc, load_ram, save_ram},
+};
+
+#[derive(Debug)]
+pub struct HuC1 {
+    rom: Vec<Vec<u8>>,
+    ram: Vec<Vec<u8>>,
+    rom_banks: usize,
+    ram_banks: usize,
+    rom_bank: u8,
+    ram_bank: u8,
+    ir_mode: bool,
+    ir: IrLink,
+}
+
+impl HuC1 {
+    pub fn new(rom: Vec<u8>, rom_banks: usize, ram_banks: usize) -> Self {
+        let rom = rom.chunks(0x4000).map(|x| x.to_vec()).collect();
+        let ram = vec![vec![0; 0x2000]; ram_banks];
+        Self {
+            rom,
+            ram,
+            rom_banks,
+            ram_banks,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_mode: false,
+            ir: IrLink::default(),
+        }
+    }
+}
+
+impl Mbc for HuC1 {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
+        let addr = addr as usize;
+        let rom_bank = self.rom_bank as usize;
+        let ram_bank = self.ram_bank as usize;
+        match addr {
+            0x0000..=0x3fff => Ok(self.rom[0][addr]),
+            0x4000..=0x7fff => Ok(self.rom[rom_bank % self.rom_banks][addr - 0x4000]),
+            0xa000..=0xbfff => {
+                if self.ir_mode {
+                    // bit 0 is set while light is being received
+                    Ok(0xc0 | self.ir.receiving() as u8)
+                } else {
+                    Ok(self.ram[ram_bank % self.ram_banks][addr - 0xa000])
+                }
+            }
+            _ => Err(EmuError::OutOfBounds {
+                device: "HuC1",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1fff => {
+                // there's no RAM enable, 0x0e switches 0xa000-0xbfff over to the IR register
+                log::debug!("HuC1: mode write: {val:02x?}");
+                self.ir_mode = val == 0x0e;
+                Ok(())
+            }
+            0x2000..=0x3fff => {
+                self.rom_bank = val & 0b0011_1111;
+                Ok(())
+            }
+            0x4000..=0x5fff => {
+                self.ram_bank = val & 0b0000_0011;
+                Ok(())
+            }
+            0x6000..=0x7fff => Ok(()),
+            0xa000..=0xbfff => {
+                if self.ir_mode {
+                    self.ir.set_led((val & 0b0000_0001) > 0);
+                } else {
+                    let ram_bank = self.ram_bank as usize;
+                    self.ram[ram_bank % self.ram_banks][addr - 0xa000] = val;
+                }
+                Ok(())
+            }
+            _ => Err(EmuError::OutOfBounds {
+                device: "HuC1",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
+        }
+    }
+
+    fn connect_ir(&mut self, link: IrLink) {
+        self.ir = link;
+    }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        Some(save_ram(&self.ram))
+    }
+
+    fn load_save(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        load_ram("HuC1", &mut self.ram, data)
+    }
+}
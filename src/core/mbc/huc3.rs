@@ -0,0 +1,232 @@
+use anyhow::anyhow;
+
+use crate::core::{
+    error::EmuError,
+    ir::IrLink,
+    mbc::{Mbc, load_ram, save_ram},
+};
+
+/// M-cycles in one minute of emulated time, the HuC3 clock only counts minutes and days
+const CYCLES_PER_MINUTE: u32 = 60 * 1_048_576;
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+#[derive(Debug)]
+pub struct HuC3 {
+    rom: Vec<Vec<u8>>,
+    ram: Vec<Vec<u8>>,
+    rom_banks: usize,
+    ram_banks: usize,
+    rom_bank: u8,
+    ram_bank: u8,
+    mode: u8,
+    ir: IrLink,
+
+    // RTC state, the MCU is driven through a command/response interface
+    command: u8,
+    argument: u8,
+    response: u8,
+    address: u8,
+    memory: Vec<u8>, // 256 nibbles
+    minutes: u16,
+    days: u16,
+    cycles: u32,
+    tone: Option<u8>,
+}
+
+impl HuC3 {
+    pub fn new(rom: Vec<u8>, rom_banks: usize, ram_banks: usize) -> Self {
+        let rom = rom.chunks(0x4000).map(|x| x.to_vec()).collect();
+        let ram = vec![vec![0; 0x2000]; ram_banks];
+        Self {
+            rom,
+            ram,
+            rom_banks,
+            ram_banks,
+            rom_bank: 1,
+            ram_bank: 0,
+            mode: 0,
+            ir: IrLink::default(),
+            command: 0,
+            argument: 0,
+            response: 0,
+            address: 0,
+            memory: vec![0; 0x100],
+            minutes: 0,
+            days: 0,
+            cycles: 0,
+            tone: None,
+        }
+    }
+
+    fn execute(&mut self) {
+        log::debug!(
+            "HuC3: command {:x?} argument {:x?} address {:02x?}",
+            self.command,
+            self.argument,
+            self.address
+        );
+        match self.command {
+            0x1 => {
+                // read and increment
+                self.response = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            }
+            0x3 => {
+                // write and increment
+                self.memory[self.address as usize] = self.argument;
+                self.address = self.address.wrapping_add(1);
+            }
+            0x4 => self.address = (self.address & 0xf0) | self.argument,
+            0x5 => self.address = (self.address & 0x0f) | (self.argument << 4),
+            0x6 => match self.argument {
+                0x0 => {
+                    // copy the clock to memory 0x00-0x05, as little endian nibbles
+                    for i in 0..3 {
+                        self.memory[i] = ((self.minutes >> (4 * i)) & 0xf) as u8;
+                        self.memory[3 + i] = ((self.days >> (4 * i)) & 0xf) as u8;
+                    }
+                }
+                0x1 => {
+                    // copy memory 0x00-0x05 to the clock
+                    let mut minutes = 0;
+                    let mut days = 0;
+                    for i in 0..3 {
+                        minutes |= (self.memory[i] as u16) << (4 * i);
+                        days |= (self.memory[3 + i] as u16) << (4 * i);
+                    }
+                    self.minutes = minutes % MINUTES_PER_DAY;
+                    self.days = days;
+                    self.cycles = 0;
+                }
+                0x2 => {
+                    // status, games expect this to always be 1
+                    self.response = 0x1;
+                }
+                0xe => {
+                    // the tone to play is picked by memory 0x27, 0 silences it
+                    let tone = self.memory[0x27];
+                    log::debug!("HuC3: tone generator: {tone:x?}");
+                    self.tone = if tone == 0 { None } else { Some(tone) };
+                }
+                arg => log::warn!("HuC3: unknown extended command: {arg:x?}"),
+            },
+            cmd => log::warn!("HuC3: unknown command: {cmd:x?}"),
+        }
+    }
+}
+
+impl Mbc for HuC3 {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
+        let addr = addr as usize;
+        let rom_bank = self.rom_bank as usize;
+        let ram_bank = self.ram_bank as usize;
+        match addr {
+            0x0000..=0x3fff => Ok(self.rom[0][addr]),
+            0x4000..=0x7fff => Ok(self.rom[rom_bank % self.rom_banks][addr - 0x4000]),
+            0xa000..=0xbfff => match self.mode {
+                0x0 | 0xa => Ok(self.ram[ram_bank % self.ram_banks][addr - 0xa000]),
+                0xc => Ok(0x80 | (self.command << 4) | self.response),
+                0xd => Ok(0xff), // semaphore, commands complete instantly so we're always ready
+                0xe => Ok(0xc0 | self.ir.receiving() as u8),
+                _ => Ok(0xff),
+            },
+            _ => Err(EmuError::OutOfBounds {
+                device: "HuC3",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1fff => {
+                log::debug!("HuC3: mode write: {val:02x?}");
+                self.mode = val & 0x0f;
+                Ok(())
+            }
+            0x2000..=0x3fff => {
+                self.rom_bank = val & 0b0111_1111;
+                Ok(())
+            }
+            0x4000..=0x5fff => {
+                self.ram_bank = val & 0b0000_0011;
+                Ok(())
+            }
+            0x6000..=0x7fff => Ok(()),
+            0xa000..=0xbfff => {
+                match self.mode {
+                    0xa => {
+                        let ram_bank = self.ram_bank as usize;
+                        self.ram[ram_bank % self.ram_banks][addr - 0xa000] = val;
+                    }
+                    0xb => {
+                        self.command = (val & 0b0111_0000) >> 4;
+                        self.argument = val & 0b0000_1111;
+                    }
+                    // clearing bit 0 of the semaphore runs the pending command
+                    0xd if (val & 0b0000_0001) == 0 => self.execute(),
+                    0xe => self.ir.set_led((val & 0b0000_0001) > 0),
+                    _ => {}
+                }
+                Ok(())
+            }
+            _ => Err(EmuError::OutOfBounds {
+                device: "HuC3",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycles += 1;
+        if self.cycles < CYCLES_PER_MINUTE {
+            return;
+        }
+        self.cycles = 0;
+        self.minutes += 1;
+        if self.minutes == MINUTES_PER_DAY {
+            self.minutes = 0;
+            self.days = (self.days + 1) & 0x0fff;
+        }
+    }
+
+    fn connect_ir(&mut self, link: IrLink) {
+        self.ir = link;
+    }
+
+    fn tone(&self) -> Option<u8> {
+        self.tone
+    }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        // the MCU's memory and clock run off the battery too, they follow the RAM
+        let mut save = save_ram(&self.ram);
+        save.extend(&self.memory);
+        save.extend(self.minutes.to_le_bytes());
+        save.extend(self.days.to_le_bytes());
+        Some(save)
+    }
+
+    fn load_save(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let ram_size = self.ram_banks * 0x2000;
+        let size = ram_size + self.memory.len() + 4;
+        if data.len() != size {
+            return Err(anyhow!(
+                "HuC3: save should be {size} bytes, got {}",
+                data.len()
+            ));
+        }
+        let (ram, rtc) = data.split_at(ram_size);
+        load_ram("HuC3", &mut self.ram, ram)?;
+        let (memory, clock) = rtc.split_at(self.memory.len());
+        self.memory.copy_from_slice(memory);
+        self.minutes = u16::from_le_bytes([clock[0], clock[1]]) % MINUTES_PER_DAY;
+        self.days = u16::from_le_bytes([clock[2], clock[3]]) & 0x0fff;
+        Ok(())
+    }
+}
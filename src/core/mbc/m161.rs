@@ -0,0 +1,61 @@
+use crate::core::{error::EmuError, mbc::Mbc};
+
+/// Mani's M161 multicart mapper, the first write picks a 32 KiB game and then the register
+/// locks until the console is reset
+#[derive(Debug)]
+pub struct M161 {
+    rom: Vec<Vec<u8>>,
+    bank: usize,
+    locked: bool,
+}
+
+impl M161 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        let rom = rom.chunks(0x8000).map(|x| x.to_vec()).collect();
+        Self {
+            rom,
+            bank: 0,
+            locked: false,
+        }
+    }
+}
+
+impl Mbc for M161 {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x7fff => {
+                let bank = &self.rom[self.bank % self.rom.len()];
+                Ok(bank.get(addr).copied().unwrap_or(0xff))
+            }
+            0xa000..=0xbfff => Ok(0xff),
+            _ => Err(EmuError::OutOfBounds {
+                device: "M161",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x4000..=0x5fff => {
+                if !self.locked {
+                    self.bank = (val & 0b0000_0111) as usize;
+                    self.locked = true;
+                    log::debug!("M161: selected game {}", self.bank);
+                }
+                Ok(())
+            }
+            0x0000..=0x3fff | 0x6000..=0x7fff | 0xa000..=0xbfff => Ok(()),
+            _ => Err(EmuError::OutOfBounds {
+                device: "M161",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
+        }
+    }
+}
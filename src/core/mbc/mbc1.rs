@@ -1,6 +1,7 @@
-use anyhow::anyhow;
-
-use crate::core::mbc::Mbc;
+use crate::core::{
+    error::EmuError,
+    mbc::{Mbc, load_ram, save_ram},
+};
 
 #[derive(Debug)]
 pub struct Mbc1 {
@@ -13,20 +14,27 @@ pub struct Mbc1 {
     ram_banks: usize,
     battery: bool,
     mode: u8,
+    multicart: bool,
 }
 
 impl Mbc for Mbc1 {
-    fn read(&self, addr: u16) -> anyhow::Result<u8> {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
         let addr = addr as usize;
         let bank1 = self.bank1 as usize;
         let bank2 = self.bank2 as usize;
+        // MBC1M only connects 4 of the bank1 bits, so bank2 starts at bit 4 instead of bit 5
+        let (bank1, bank2) = if self.multicart {
+            (bank1 & 0b0000_1111, bank2 << 4)
+        } else {
+            (bank1, bank2 << 5)
+        };
         match addr {
             0x0000..=0x3fff => match self.mode {
                 0 => Ok(self.rom[0][addr]),
-                1 => Ok(self.rom[(bank2 << 5) % self.rom_banks][addr]),
+                1 => Ok(self.rom[bank2 % self.rom_banks][addr]),
                 _ => unreachable!("Mbc1: invalid addressing mode"),
             },
-            0x4000..=0x7fff => Ok(self.rom[((bank2 << 5) | bank1) % self.rom_banks][addr - 0x4000]),
+            0x4000..=0x7fff => Ok(self.rom[(bank2 | bank1) % self.rom_banks][addr - 0x4000]),
             0xa000..=0xbfff => {
                 if self.ram_enable {
                     match self.mode {
@@ -38,11 +46,16 @@ impl Mbc for Mbc1 {
                     Ok(0xff)
                 }
             }
-            _ => Err(anyhow!("Mbc1: invalid read: {addr:04x?}")),
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mbc1",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
         }
     }
 
-    fn write(&mut self, addr: u16, val: u8) -> anyhow::Result<()> {
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
         let addr = addr as usize;
         match addr {
             0x0000..=0x1fff => {
@@ -62,13 +75,11 @@ impl Mbc for Mbc1 {
                 Ok(())
             }
             0x4000..=0x5fff => {
-                if self.ram_banks == 1 {
-                    return Ok(());
-                }
+                // bank2 selects either the RAM bank or bits 5-6 of the ROM bank, depending on
+                // what the cartridge has wired up
                 log::debug!("Mbc1: write bank2: {val:02x?}");
                 let val = val & 0b0000_0011;
                 self.bank2 = val;
-                // TODO: upper rom bank switches
                 Ok(())
             }
             0x6000..=0x7fff => {
@@ -81,7 +92,7 @@ impl Mbc for Mbc1 {
                     let bank2 = self.bank2 as usize;
                     match self.mode {
                         0 => self.ram[0][addr - 0xa000] = val,
-                        1 => self.ram[bank2][addr - 0xa000] = val,
+                        1 => self.ram[bank2 % self.ram_banks][addr - 0xa000] = val,
                         _ => unreachable!("Mbc1: invalid addressing mode"),
                     }
                     Ok(())
@@ -89,14 +100,34 @@ impl Mbc for Mbc1 {
                     Ok(())
                 }
             }
-            _ => Err(anyhow!("Mbc1: invalid write: {addr:04x?}")),
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mbc1",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
         }
     }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        self.battery.then(|| save_ram(&self.ram))
+    }
+
+    fn load_save(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        load_ram("Mbc1", &mut self.ram, data)
+    }
 }
 
+/// Location of the Nintendo logo in the cartridge header
+const LOGO: std::ops::Range<usize> = 0x0104..0x0134;
+
 impl Mbc1 {
     pub fn new(rom: Vec<u8>, rom_banks: usize, ram_banks: usize, battery: bool) -> Self {
-        let rom = rom.chunks(0x4000).map(|x| x.to_vec()).collect();
+        let rom: Vec<Vec<u8>> = rom.chunks(0x4000).map(|x| x.to_vec()).collect();
+        let multicart = Self::is_multicart(&rom);
+        if multicart {
+            log::info!("Mbc1: detected MBC1M multicart");
+        }
         let ram = vec![vec![0; 0x2000]; ram_banks];
         Self {
             rom,
@@ -108,6 +139,19 @@ impl Mbc1 {
             bank1: 1,
             bank2: 0,
             mode: 0,
+            multicart,
+        }
+    }
+
+    /// MBC1M carts are 8 Mbit MBC1 carts where every game has its own header, so bank 0x10 (the
+    /// first bank of the second game) has a copy of the Nintendo logo
+    fn is_multicart(rom: &[Vec<u8>]) -> bool {
+        if rom.len() != 64 {
+            return false;
+        }
+        match (rom[0].get(LOGO), rom[0x10].get(LOGO)) {
+            (Some(logo), Some(other)) => logo == other,
+            _ => false,
         }
     }
 }
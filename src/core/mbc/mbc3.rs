@@ -1,6 +1,7 @@
-use anyhow::anyhow;
-
-use crate::core::mbc::Mbc;
+use crate::core::{
+    error::EmuError,
+    mbc::{Mbc, load_ram, save_ram},
+};
 
 #[derive(Debug)]
 pub struct Mbc3 {
@@ -12,6 +13,7 @@ pub struct Mbc3 {
     rom_bank: u8,
     ram_bank: u8,
     ram_enable: bool,
+    mbc30: bool,
 }
 
 impl Mbc3 {
@@ -21,6 +23,7 @@ impl Mbc3 {
         ram_banks: usize,
         battery: bool,
         timer: bool,
+        mbc30: bool,
     ) -> Self {
         let rom = rom.chunks(0x4000).map(|x| x.to_vec()).collect();
         let ram = vec![vec![0; 0x2000]; ram_banks];
@@ -33,49 +36,70 @@ impl Mbc3 {
             rom_bank: 1,
             ram_bank: 0,
             ram_enable: false,
+            mbc30,
         }
     }
+
+    /// MBC30 wires up all 8 ROM bank bits and 3 RAM bank bits, MBC3 only 7 and 2
+    fn rom_bank_mask(&self) -> u8 {
+        if self.mbc30 { 0b1111_1111 } else { 0b0111_1111 }
+    }
+
+    fn ram_bank_mask(&self) -> u8 {
+        if self.mbc30 { 0b0000_0111 } else { 0b0000_0011 }
+    }
 }
 
 impl Mbc for Mbc3 {
-    fn read(&self, addr: u16) -> anyhow::Result<u8> {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
         let addr = addr as usize;
         let rom_bank = self.rom_bank as usize;
-        let ram_bank = self.ram_bank as usize;
+        let ram_bank = (self.ram_bank & self.ram_bank_mask()) as usize;
         match addr {
             0x0000..=0x3fff => Ok(self.rom[0][addr]),
-            0x4000..=0x7fff => Ok(self.rom[rom_bank][addr - 0x4000]),
+            0x4000..=0x7fff => Ok(self.rom[rom_bank % self.rom_banks][addr - 0x4000]),
             0xa000..=0xbfff => {
                 if self.ram_enable {
                     match self.ram_bank {
                         0x0..=0x07 => {
                             // ram banks
-                            Ok(self.ram[ram_bank][addr - 0xa000])
+                            Ok(self.ram[ram_bank % self.ram_banks][addr - 0xa000])
                         }
                         0x08..=0x0c => {
                             // TODO: rtc banks
                             Ok(0xff)
                         }
-                        _ => Err(anyhow!("Mbc3: bad ram bank: 0x{ram_bank:02x?}")),
+                        bank => Err(EmuError::BadRamBank {
+                            mapper: "Mbc3",
+                            pc: None,
+                            addr: addr as u16,
+                            bank,
+                        }),
                     }
                 } else {
                     Ok(0xff)
                 }
             }
-            _ => Err(anyhow!("Mbc3: invalid read: 0x{addr:04x?}")),
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mbc3",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
         }
     }
 
-    fn write(&mut self, addr: u16, val: u8) -> anyhow::Result<()> {
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
         let addr = addr as usize;
-        let ram_bank = self.ram_bank as usize;
+        let ram_bank = (self.ram_bank & self.ram_bank_mask()) as usize;
         match addr {
             0x0000..=0x1fff => {
                 self.ram_enable = (val & 0x0f) == 0x0a;
                 Ok(())
             }
             0x2000..=0x3fff => {
-                self.rom_bank = if val == 0 { 0x01 } else { val & 0b0111_1111 };
+                let val = val & self.rom_bank_mask();
+                self.rom_bank = if val == 0 { 0x01 } else { val };
                 Ok(())
             }
             0x4000..=0x5fff => {
@@ -91,17 +115,37 @@ impl Mbc for Mbc3 {
                     match self.ram_bank {
                         0x0..=0x07 => {
                             // ram banks
-                            self.ram[ram_bank][addr - 0xa000] = val;
+                            self.ram[ram_bank % self.ram_banks][addr - 0xa000] = val;
                         }
                         0x08..=0x0c => {
                             // TODO: rtc banks
                         }
-                        _ => return Err(anyhow!("Mbc3: bad ram bank: 0x{ram_bank:02x?}")),
+                        bank => {
+                            return Err(EmuError::BadRamBank {
+                                mapper: "Mbc3",
+                                pc: None,
+                                addr: addr as u16,
+                                bank,
+                            });
+                        }
                     }
                 }
                 Ok(())
             }
-            _ => Err(anyhow!("Mbc3: invalid write: 0x{addr:04x?}")),
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mbc3",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
         }
     }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        self.battery.then(|| save_ram(&self.ram))
+    }
+
+    fn load_save(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        load_ram("Mbc3", &mut self.ram, data)
+    }
 }
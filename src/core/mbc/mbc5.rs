@@ -1,6 +1,7 @@
-use anyhow::anyhow;
-
-use crate::core::mbc::Mbc;
+use crate::core::{
+    error::EmuError,
+    mbc::{Mbc, RumbleEvent, load_ram, save_ram},
+};
 
 #[derive(Debug)]
 pub struct Mbc5 {
@@ -13,10 +14,13 @@ pub struct Mbc5 {
     rom_banks: usize,
     ram_banks: usize,
     battery: bool,
+    rumble: bool,
+    motor: bool,
+    rumble_events: Vec<RumbleEvent>,
 }
 
 impl Mbc for Mbc5 {
-    fn read(&self, addr: u16) -> anyhow::Result<u8> {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
         let addr = addr as usize;
         let bank = (((self.rom_bank_high & 1) as usize) << 8) | self.rom_bank_low as usize;
         match addr {
@@ -24,7 +28,7 @@ impl Mbc for Mbc5 {
             0x4000..=0x7fff => Ok(self.rom[bank % self.rom_banks][addr - 0x4000]),
             0xa000..=0xbfff => {
                 if self.ram_enable {
-                    Ok(self.ram[self.ram_bank as usize][addr - 0xa000])
+                    Ok(self.ram[self.ram_bank as usize % self.ram_banks][addr - 0xa000])
                 } else {
                     Ok(0xff)
                 }
@@ -33,7 +37,7 @@ impl Mbc for Mbc5 {
         }
     }
 
-    fn write(&mut self, addr: u16, val: u8) -> anyhow::Result<()> {
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
         let addr = addr as usize;
         match addr {
             0x0000..=0x1fff => {
@@ -56,14 +60,27 @@ impl Mbc for Mbc5 {
             }
             0x4000..=0x5fff => {
                 log::debug!("Mbc5: write ram bank: {val:02x?}");
-                let val = val & 0x0f;
-                self.ram_bank = val;
+                if self.rumble {
+                    // bit 3 drives the rumble motor instead of selecting a bank
+                    let motor = (val & 0b0000_1000) > 0;
+                    if motor != self.motor {
+                        self.motor = motor;
+                        self.rumble_events.push(if motor {
+                            RumbleEvent::On
+                        } else {
+                            RumbleEvent::Off
+                        });
+                    }
+                    self.ram_bank = val & 0b0000_0111;
+                } else {
+                    self.ram_bank = val & 0x0f;
+                }
                 Ok(())
             }
 
             0xa000..=0xbfff => {
                 if self.ram_enable {
-                    self.ram[self.ram_bank as usize][addr - 0xa000] = val;
+                    self.ram[self.ram_bank as usize % self.ram_banks][addr - 0xa000] = val;
                     Ok(())
                 } else {
                     Ok(())
@@ -72,10 +89,28 @@ impl Mbc for Mbc5 {
             _ => Ok(()), // invalid, discard the write
         }
     }
+
+    fn rumble_events(&mut self) -> Vec<RumbleEvent> {
+        std::mem::take(&mut self.rumble_events)
+    }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        self.battery.then(|| save_ram(&self.ram))
+    }
+
+    fn load_save(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        load_ram("Mbc5", &mut self.ram, data)
+    }
 }
 
 impl Mbc5 {
-    pub fn new(rom: Vec<u8>, rom_banks: usize, ram_banks: usize, battery: bool) -> Self {
+    pub fn new(
+        rom: Vec<u8>,
+        rom_banks: usize,
+        ram_banks: usize,
+        battery: bool,
+        rumble: bool,
+    ) -> Self {
         let rom = rom.chunks(0x4000).map(|x| x.to_vec()).collect();
         let ram = vec![vec![0; 0x2000]; ram_banks];
         Self {
@@ -88,6 +123,9 @@ impl Mbc5 {
             ram_bank: 0,
             rom_bank_low: 1,
             rom_bank_high: 0,
+            rumble,
+            motor: false,
+            rumble_events: Vec::new(),
         }
     }
 }
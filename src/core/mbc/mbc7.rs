@@ -0,0 +1,324 @@
+use anyhow::anyhow;
+
+use crate::core::{error::EmuError, mbc::Mbc};
+
+/// Accelerometer reading when the cartridge is held flat
+const ACCEL_CENTER: u16 = 0x81d0;
+/// Roughly how far the accelerometer reading moves for 1g of tilt
+const ACCEL_RANGE: f32 = 0x70 as f32;
+/// The 93LC56 is organised as 128 16-bit words
+const EEPROM_WORDS: usize = 128;
+
+#[derive(Debug)]
+enum EepromState {
+    /// Waiting for a start bit
+    Idle,
+    /// Shifting in the 2 opcode and 8 address bits
+    Command,
+    /// Shifting out a word, starting with a dummy 0 bit
+    Read { addr: usize, bit: u8 },
+    /// Shifting in a word for WRITE, or WRAL if `addr` is None
+    Write { addr: Option<usize> },
+    /// Command finished, nothing happens until CS is brought low
+    Done,
+}
+
+/// Bit-banged 93LC56 serial EEPROM
+#[derive(Debug)]
+struct Eeprom {
+    data: Vec<u16>,
+    cs: bool,
+    clk: bool,
+    di: bool,
+    dout: bool,
+    write_enable: bool,
+    state: EepromState,
+    shift: u16,
+    bits: u8,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Self {
+            data: vec![0xffff; EEPROM_WORDS],
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+            write_enable: false,
+            state: EepromState::Idle,
+            shift: 0,
+            bits: 0,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        ((self.cs as u8) << 7) | ((self.clk as u8) << 6) | ((self.di as u8) << 1) | self.dout as u8
+    }
+
+    fn write(&mut self, val: u8) {
+        let cs = (val & 0b1000_0000) > 0;
+        let clk = (val & 0b0100_0000) > 0;
+        self.di = (val & 0b0000_0010) > 0;
+
+        if !cs {
+            // deselecting the chip aborts whatever it was doing
+            self.state = EepromState::Idle;
+            self.dout = true;
+        } else if clk && !self.clk {
+            // commands are clocked in on the rising edge
+            self.clock();
+        }
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    fn clock(&mut self) {
+        match self.state {
+            EepromState::Idle => {
+                if self.di {
+                    log::trace!("Eeprom: start bit");
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            }
+            EepromState::Command => {
+                self.shift_in();
+                if self.bits == 10 {
+                    self.command();
+                }
+            }
+            EepromState::Read { addr, bit } => {
+                let word = self.data[addr];
+                self.dout = (word & (0x8000 >> bit)) > 0;
+                self.state = if bit == 15 {
+                    // sequential reads roll over into the next word
+                    EepromState::Read {
+                        addr: (addr + 1) % EEPROM_WORDS,
+                        bit: 0,
+                    }
+                } else {
+                    EepromState::Read { addr, bit: bit + 1 }
+                };
+            }
+            EepromState::Write { addr } => {
+                self.shift_in();
+                if self.bits == 16 {
+                    if self.write_enable {
+                        match addr {
+                            Some(addr) => self.data[addr] = self.shift,
+                            None => self.data.fill(self.shift),
+                        }
+                    }
+                    log::debug!("Eeprom: write {addr:?} = {:04x?}", self.shift);
+                    self.dout = true;
+                    self.state = EepromState::Done;
+                }
+            }
+            EepromState::Done => {}
+        }
+    }
+
+    fn shift_in(&mut self) {
+        self.shift = (self.shift << 1) | self.di as u16;
+        self.bits += 1;
+    }
+
+    fn command(&mut self) {
+        let opcode = (self.shift >> 8) & 0b11;
+        // opcode 00 uses the top 2 address bits to pick between EWEN/EWDS/ERAL/WRAL
+        let extended = (self.shift >> 6) & 0b11;
+        // the top address bit is a don't care on the 93LC56
+        let addr = (self.shift & 0x7f) as usize;
+        self.shift = 0;
+        self.bits = 0;
+        self.state = match opcode {
+            0b10 => {
+                log::debug!("Eeprom: READ {addr:02x?}");
+                // a dummy 0 bit comes out before the data
+                self.dout = false;
+                EepromState::Read { addr, bit: 0 }
+            }
+            0b01 => EepromState::Write { addr: Some(addr) },
+            0b11 => {
+                log::debug!("Eeprom: ERASE {addr:02x?}");
+                if self.write_enable {
+                    self.data[addr] = 0xffff;
+                }
+                self.dout = true;
+                EepromState::Done
+            }
+            _ => match extended {
+                0b11 => {
+                    log::debug!("Eeprom: EWEN");
+                    self.write_enable = true;
+                    EepromState::Done
+                }
+                0b00 => {
+                    log::debug!("Eeprom: EWDS");
+                    self.write_enable = false;
+                    EepromState::Done
+                }
+                0b10 => {
+                    log::debug!("Eeprom: ERAL");
+                    if self.write_enable {
+                        self.data.fill(0xffff);
+                    }
+                    self.dout = true;
+                    EepromState::Done
+                }
+                _ => EepromState::Write { addr: None },
+            },
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct Mbc7 {
+    rom: Vec<Vec<u8>>,
+    rom_banks: usize,
+    rom_bank: u8,
+    ram_enable_1: bool,
+    ram_enable_2: bool,
+    tilt: (f32, f32),
+    accel_x: u16,
+    accel_y: u16,
+    latch_ready: bool,
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<u8>, rom_banks: usize) -> Self {
+        let rom = rom.chunks(0x4000).map(|x| x.to_vec()).collect();
+        Self {
+            rom,
+            rom_banks,
+            rom_bank: 1,
+            ram_enable_1: false,
+            ram_enable_2: false,
+            tilt: (0.0, 0.0),
+            accel_x: 0x8000,
+            accel_y: 0x8000,
+            latch_ready: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable_1 && self.ram_enable_2
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
+        let addr = addr as usize;
+        let rom_bank = self.rom_bank as usize;
+        match addr {
+            0x0000..=0x3fff => Ok(self.rom[0][addr]),
+            0x4000..=0x7fff => Ok(self.rom[rom_bank % self.rom_banks][addr - 0x4000]),
+            0xa000..=0xafff => {
+                if !self.ram_enabled() {
+                    return Ok(0xff);
+                }
+                match addr & 0x00f0 {
+                    0x20 => Ok((self.accel_x & 0xff) as u8),
+                    0x30 => Ok((self.accel_x >> 8) as u8),
+                    0x40 => Ok((self.accel_y & 0xff) as u8),
+                    0x50 => Ok((self.accel_y >> 8) as u8),
+                    0x60 => Ok(0x00),
+                    0x80 => Ok(self.eeprom.read()),
+                    _ => Ok(0xff),
+                }
+            }
+            0xb000..=0xbfff => Ok(0xff),
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mbc7",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable_1 = val == 0x0a;
+                Ok(())
+            }
+            0x2000..=0x3fff => {
+                self.rom_bank = val & 0b0111_1111;
+                Ok(())
+            }
+            0x4000..=0x5fff => {
+                self.ram_enable_2 = val == 0x40;
+                Ok(())
+            }
+            0x6000..=0x7fff => Ok(()),
+            0xa000..=0xafff => {
+                if !self.ram_enabled() {
+                    return Ok(());
+                }
+                match addr & 0x00f0 {
+                    0x00 if val == 0x55 => {
+                        // erase the latched values before a new latch
+                        self.accel_x = 0x8000;
+                        self.accel_y = 0x8000;
+                        self.latch_ready = true;
+                    }
+                    0x10 if val == 0xaa && self.latch_ready => {
+                        let (x, y) = self.tilt;
+                        self.accel_x = (ACCEL_CENTER as f32 - x * ACCEL_RANGE) as u16;
+                        self.accel_y = (ACCEL_CENTER as f32 - y * ACCEL_RANGE) as u16;
+                        self.latch_ready = false;
+                        log::trace!(
+                            "Mbc7: latched accelerometer: {:04x?} {:04x?}",
+                            self.accel_x,
+                            self.accel_y
+                        );
+                    }
+                    0x80 => self.eeprom.write(val),
+                    _ => {}
+                }
+                Ok(())
+            }
+            0xb000..=0xbfff => Ok(()),
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mbc7",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
+        }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0));
+    }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        Some(
+            self.eeprom
+                .data
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect(),
+        )
+    }
+
+    fn load_save(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        if data.len() != EEPROM_WORDS * 2 {
+            return Err(anyhow!(
+                "Mbc7: save should be {} bytes, got {}",
+                EEPROM_WORDS * 2,
+                data.len()
+            ));
+        }
+        for (word, bytes) in self.eeprom.data.iter_mut().zip(data.chunks(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use crate::core::{
+    error::EmuError,
+    mbc::{Mbc, load_ram, save_ram},
+};
+
+/// MMM01 multicart mapper. It boots into the menu in the last 32 KiB of ROM, the menu then sets
+/// up the outer bank bits for a game and maps it in, after which those bits can't be changed
+/// until reset and the cart behaves like an MBC1 confined to the game's slice of ROM
+#[derive(Debug)]
+pub struct Mmm01 {
+    rom: Vec<Vec<u8>>,
+    ram: Vec<Vec<u8>>,
+    rom_banks: usize,
+    ram_banks: usize,
+    battery: bool,
+    mapped: bool,
+    ram_enable: bool,
+    rom_low: u8,
+    rom_mid: u8,
+    rom_high: u8,
+    ram_low: u8,
+    ram_high: u8,
+    /// ROM bank bits 1-4 that are protected from writes once mapped
+    rom_mask: u8,
+}
+
+impl Mmm01 {
+    pub fn new(rom: Vec<u8>, ram_banks: usize, battery: bool) -> Self {
+        // the header describes the menu or first game, the real size only comes from the file
+        let rom: Vec<Vec<u8>> = rom.chunks_exact(0x4000).map(|x| x.to_vec()).collect();
+        let ram = vec![vec![0; 0x2000]; ram_banks];
+        Self {
+            rom_banks: rom.len(),
+            rom,
+            ram,
+            ram_banks,
+            battery,
+            mapped: false,
+            ram_enable: false,
+            rom_low: 0,
+            rom_mid: 0,
+            rom_high: 0,
+            ram_low: 0,
+            ram_high: 0,
+            rom_mask: 0,
+        }
+    }
+
+    fn outer_bank(&self) -> usize {
+        ((self.rom_high as usize) << 7) | ((self.rom_mid as usize) << 5)
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x7fff if !self.mapped => {
+                // the menu lives in the last two banks
+                let bank = self.rom_banks.saturating_sub(2) + addr / 0x4000;
+                Ok(self.rom[bank % self.rom_banks][addr % 0x4000])
+            }
+            0x0000..=0x3fff => {
+                let bank = self.outer_bank() | (self.rom_low & (self.rom_mask << 1)) as usize;
+                Ok(self.rom[bank % self.rom_banks][addr])
+            }
+            0x4000..=0x7fff => {
+                // like MBC1, bank 0 in the unprotected bits reads as bank 1
+                let mut rom_low = self.rom_low;
+                if rom_low & !(self.rom_mask << 1) & 0b0001_1111 == 0 {
+                    rom_low |= 1;
+                }
+                let bank = self.outer_bank() | rom_low as usize;
+                Ok(self.rom[bank % self.rom_banks][addr - 0x4000])
+            }
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    let bank = ((self.ram_high << 2) | self.ram_low) as usize;
+                    Ok(self.ram[bank % self.ram_banks][addr - 0xa000])
+                } else {
+                    Ok(0xff)
+                }
+            }
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mmm01",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable = (val & 0x0f) == 0x0a;
+                if !self.mapped && (val & 0b0100_0000) > 0 {
+                    log::debug!(
+                        "Mmm01: mapping game at bank {:02x?}, mask {:x?}",
+                        self.outer_bank(),
+                        self.rom_mask
+                    );
+                    self.mapped = true;
+                }
+                Ok(())
+            }
+            0x2000..=0x3fff => {
+                let protected = if self.mapped { self.rom_mask << 1 } else { 0 };
+                self.rom_low = (self.rom_low & protected) | (val & !protected & 0b0001_1111);
+                if !self.mapped {
+                    self.rom_mid = (val & 0b0110_0000) >> 5;
+                }
+                Ok(())
+            }
+            0x4000..=0x5fff => {
+                self.ram_low = val & 0b0000_0011;
+                if !self.mapped {
+                    self.ram_high = (val & 0b0000_1100) >> 2;
+                    self.rom_high = (val & 0b0011_0000) >> 4;
+                }
+                Ok(())
+            }
+            0x6000..=0x7fff => {
+                if !self.mapped {
+                    self.rom_mask = (val & 0b0011_1100) >> 2;
+                }
+                Ok(())
+            }
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    let bank = ((self.ram_high << 2) | self.ram_low) as usize;
+                    self.ram[bank % self.ram_banks][addr - 0xa000] = val;
+                }
+                Ok(())
+            }
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mmm01",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
+        }
+    }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        self.battery.then(|| save_ram(&self.ram))
+    }
+
+    fn load_save(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        load_ram("Mmm01", &mut self.ram, data)
+    }
+}
@@ -1,16 +1,107 @@
 use anyhow::anyhow;
 use std::fmt::Debug;
 
-use crate::core::mbc::{mbc1::Mbc1, mbc3::Mbc3, mbc5::Mbc5, rom_only::RomOnly};
+use crate::core::{
+    error::EmuError,
+    ir::IrLink,
+    mbc::{
+        camera::PocketCamera, huc1::HuC1, huc3::HuC3, info::RomInfo, m161::M161, mbc1::Mbc1,
+        mbc3::Mbc3, mbc5::Mbc5, mbc7::Mbc7, mmm01::Mmm01, rom_only::RomOnly, sachen::SachenMmc1,
+        wisdom_tree::WisdomTree,
+    },
+};
 
+pub mod camera;
+pub mod huc1;
+pub mod huc3;
+pub mod info;
+pub mod m161;
 pub mod mbc1;
 pub mod mbc3;
 pub mod mbc5;
+pub mod mbc7;
+pub mod mmm01;
 pub mod rom_only;
+pub mod sachen;
+pub mod wisdom_tree;
+
+/// The logo every licensed cartridge carries at 0x0104
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+/// Rumble motor state changes, in the order the game made them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RumbleEvent {
+    On,
+    Off,
+}
 
 pub trait Mbc: Debug {
-    fn read(&self, addr: u16) -> anyhow::Result<u8>;
-    fn write(&mut self, addr: u16, val: u8) -> anyhow::Result<()>;
+    fn read(&self, addr: u16) -> Result<u8, EmuError>;
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError>;
+
+    /// Drains the rumble events since the last call, only rumble carts will produce any
+    fn rumble_events(&mut self) -> Vec<RumbleEvent> {
+        Vec::new()
+    }
+
+    /// Sets the accelerometer tilt for carts that have one, each axis ranges from -1.0 to 1.0
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// Dumps the battery-backed save data, if the cart has any
+    fn save(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores save data previously dumped with `save`
+    fn load_save(&mut self, _data: &[u8]) -> anyhow::Result<()> {
+        Err(anyhow!("Mbc: cartridge doesn't support saves"))
+    }
+
+    /// Clocks every M-cycle, for carts with their own timekeeping
+    fn clock(&mut self) {}
+
+    /// Connects the cart's IR LED and receiver to a link
+    fn connect_ir(&mut self, _link: IrLink) {}
+
+    /// The tone the cart's speaker is playing, if it has one
+    fn tone(&self) -> Option<u8> {
+        None
+    }
+
+    /// Sets the grayscale frames a camera cart's sensor sees, one per capture
+    fn set_camera_frames(&mut self, _frames: Vec<Vec<u8>>) {}
+
+    /// Photos saved in a camera cart's SRAM, as 2bpp pixels
+    fn photos(&self) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+}
+
+/// Battery-backed SRAM as a save, the banks one after another like most emulators' .sav files
+fn save_ram(ram: &[Vec<u8>]) -> Vec<u8> {
+    ram.concat()
+}
+
+/// Restores SRAM dumped with `save_ram`, a save for a different size of RAM is refused
+fn load_ram(device: &str, ram: &mut [Vec<u8>], data: &[u8]) -> anyhow::Result<()> {
+    let size: usize = ram.iter().map(Vec::len).sum();
+    if data.len() != size {
+        return Err(anyhow!(
+            "{device}: save should be {size} bytes, got {}",
+            data.len()
+        ));
+    }
+    let mut data = data;
+    for bank in ram {
+        let (chunk, rest) = data.split_at(bank.len());
+        bank.copy_from_slice(chunk);
+        data = rest;
+    }
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -20,12 +111,19 @@ pub struct CartridgeHeader {
     pub rom_banks: usize,
     pub ram_banks: usize,
     pub mbc: Box<dyn Mbc>,
+    pub info: RomInfo,
 }
 
 impl CartridgeHeader {
-    pub fn new(rom: &Vec<u8>) -> anyhow::Result<Self> {
+    pub fn new(rom: &[u8]) -> anyhow::Result<Self> {
+        if rom.len() < 0x150 {
+            return Err(anyhow!(
+                "CartridgeHeader: ROM is too small to have a header ({} bytes)",
+                rom.len()
+            ));
+        }
         let title = String::from_utf8_lossy(&rom[0x134..=0x143]).to_string();
-        let cartridge_type = rom[0x147].try_into()?;
+        let cartridge_type = detect_mapper(rom);
         let rom_banks = match rom[0x148] {
             0x00 => 2,
             0x01 => 4,
@@ -41,30 +139,97 @@ impl CartridgeHeader {
             0x54 => 96,
             _ => return Err(anyhow!("CartridgeHeader: unknown ROM size")),
         };
-        let ram_banks = match rom[0x149] {
-            0x00 => 1, // technically zero, but making it 1 here for compat with no-mapper
-            0x02 => 1,
-            0x03 => 4,
-            0x04 => 16,
-            0x05 => 8,
-            _ => return Err(anyhow!("CartridgeHeader: unknown RAM size")),
-        };
+        let ram_banks = ram_bank_count(rom[0x149])?;
+        let info = RomInfo::new(rom);
+        for problem in &info.problems {
+            log::warn!("CartridgeHeader: {problem}");
+        }
+        if rom.len() < rom_banks * 0x4000 {
+            return Err(anyhow!(
+                "CartridgeHeader: ROM is {} bytes but the header says it has {rom_banks} banks",
+                rom.len()
+            ));
+        }
+
+        // MBC30 shares its cartridge type with MBC3, the only way to tell them apart is that
+        // MBC3 can't address more than 128 ROM banks or 4 RAM banks
+        let mbc30 = rom_banks > 128 || ram_banks > 4;
 
         let mbc: Box<dyn Mbc> = match cartridge_type {
-            Mapper::RomOnly => Box::new(RomOnly::try_from(rom.clone())?),
-            Mapper::Mbc1 => Box::new(Mbc1::new(rom.clone(), rom_banks, ram_banks, false)),
-            Mapper::Mbc1Ram => Box::new(Mbc1::new(rom.clone(), rom_banks, ram_banks, false)),
-            Mapper::Mbc1RamBattery => Box::new(Mbc1::new(rom.clone(), rom_banks, ram_banks, true)),
-            Mapper::Mbc3RamBattery => {
-                Box::new(Mbc3::new(rom.clone(), rom_banks, ram_banks, true, false))
+            Mapper::RomOnly => Box::new(RomOnly::try_from(rom.to_vec())?),
+            Mapper::Mbc1 => Box::new(Mbc1::new(rom.to_vec(), rom_banks, ram_banks, false)),
+            Mapper::Mbc1Ram => Box::new(Mbc1::new(rom.to_vec(), rom_banks, ram_banks, false)),
+            Mapper::Mbc1RamBattery => Box::new(Mbc1::new(rom.to_vec(), rom_banks, ram_banks, true)),
+            Mapper::Mbc3TimerBattery => Box::new(Mbc3::new(
+                rom.to_vec(),
+                rom_banks,
+                ram_banks,
+                true,
+                true,
+                mbc30,
+            )),
+            Mapper::Mbc3Ram => Box::new(Mbc3::new(
+                rom.to_vec(),
+                rom_banks,
+                ram_banks,
+                false,
+                false,
+                mbc30,
+            )),
+            Mapper::Mbc3RamBattery => Box::new(Mbc3::new(
+                rom.to_vec(),
+                rom_banks,
+                ram_banks,
+                true,
+                false,
+                mbc30,
+            )),
+            Mapper::Mbc3TimerRamBattery => Box::new(Mbc3::new(
+                rom.to_vec(),
+                rom_banks,
+                ram_banks,
+                true,
+                true,
+                mbc30,
+            )),
+            Mapper::Mbc3 => Box::new(Mbc3::new(
+                rom.to_vec(),
+                rom_banks,
+                ram_banks,
+                false,
+                false,
+                mbc30,
+            )),
+            Mapper::Mbc5 => Box::new(Mbc5::new(rom.to_vec(), rom_banks, ram_banks, false, false)),
+            Mapper::Mbc5RamBattery => {
+                Box::new(Mbc5::new(rom.to_vec(), rom_banks, ram_banks, true, false))
+            }
+            Mapper::Mbc5Rumble => {
+                Box::new(Mbc5::new(rom.to_vec(), rom_banks, ram_banks, false, true))
+            }
+            Mapper::Mbc5RumbleRam => {
+                Box::new(Mbc5::new(rom.to_vec(), rom_banks, ram_banks, false, true))
             }
-            Mapper::Mbc3TimerRamBattery => {
-                Box::new(Mbc3::new(rom.clone(), rom_banks, ram_banks, true, true))
+            Mapper::Mbc5RumbleRamBattery => {
+                Box::new(Mbc5::new(rom.to_vec(), rom_banks, ram_banks, true, true))
             }
-            Mapper::Mbc3 => Box::new(Mbc3::new(rom.clone(), rom_banks, ram_banks, false, false)),
-            Mapper::Mbc5 => Box::new(Mbc5::new(rom.clone(), rom_banks, ram_banks, false)),
-            Mapper::Mbc5RamBattery => Box::new(Mbc5::new(rom.clone(), rom_banks, ram_banks, true)),
-            m => todo!("mmu: unimplemented mapper: {m:?}"),
+            Mapper::Mbc7SensorRumbleRamBattery => Box::new(Mbc7::new(rom.to_vec(), rom_banks)),
+            Mapper::PocketCamera => Box::new(PocketCamera::new(rom.to_vec(), rom_banks, ram_banks)),
+            Mapper::HuC3 => Box::new(HuC3::new(rom.to_vec(), rom_banks, ram_banks)),
+            Mapper::HuC1RamBattery => Box::new(HuC1::new(rom.to_vec(), rom_banks, ram_banks)),
+            Mapper::Mmm01 | Mapper::Mmm01Ram | Mapper::Mmm01RamBattery => {
+                // the RAM is the whole cart's, so its size is in the menu header like the type
+                let menu = rom.len() - 0x8000;
+                let battery = matches!(cartridge_type, Mapper::Mmm01RamBattery);
+                Box::new(Mmm01::new(
+                    rom.to_vec(),
+                    ram_bank_count(rom[menu + 0x149])?,
+                    battery,
+                ))
+            }
+            Mapper::WisdomTree => Box::new(WisdomTree::new(rom.to_vec())),
+            Mapper::M161 => Box::new(M161::new(rom.to_vec())),
+            Mapper::SachenMmc1 => Box::new(SachenMmc1::new(rom.to_vec())),
         };
 
         let header = CartridgeHeader {
@@ -73,12 +238,72 @@ impl CartridgeHeader {
             rom_banks,
             ram_banks,
             mbc,
+            info,
         };
         log::info!("CartridgeHeader: new: {header:?}");
         Ok(header)
     }
 }
 
+/// RAM banks for the header's RAM size byte at 0x149
+fn ram_bank_count(size: u8) -> anyhow::Result<usize> {
+    match size {
+        0x00 => Ok(1), // technically zero, but making it 1 here for compat with no-mapper
+        0x02 => Ok(1),
+        0x03 => Ok(4),
+        0x04 => Ok(16),
+        0x05 => Ok(8),
+        _ => Err(anyhow!("CartridgeHeader: unknown RAM size")),
+    }
+}
+
+/// Works out which mapper a cartridge uses. Unlicensed carts often lie about it in the header,
+/// so known ones are recognised from the ROM first and unknown types get a best guess
+fn detect_mapper(rom: &[u8]) -> Mapper {
+    // Wisdom Tree carts claim to be ROM only but credit themselves in the ROM
+    let start = &rom[..rom.len().min(0x8000)];
+    let wisdom_tree = [b"WISDOM TREE".as_slice(), b"WISDOM\0TREE".as_slice()]
+        .iter()
+        .any(|needle| start.windows(needle.len()).any(|w| w == *needle));
+    if wisdom_tree {
+        return Mapper::WisdomTree;
+    }
+
+    // Sachen's MMC1 sets A7 on header reads before it unlocks, so the real logo sits at 0x0184
+    if rom.get(0x0184..0x01b4) == Some(NINTENDO_LOGO.as_slice()) {
+        return Mapper::SachenMmc1;
+    }
+
+    // the MMM01 header is in the menu at the end of the ROM, the start is the first game's
+    if rom.len() > 0x8000 {
+        let menu = rom.len() - 0x8000;
+        if let Some(Ok(m @ (Mapper::Mmm01 | Mapper::Mmm01Ram | Mapper::Mmm01RamBattery))) =
+            rom.get(menu + 0x147).map(|x| Mapper::try_from(*x))
+        {
+            return m;
+        }
+    }
+
+    // Mani's 4 in 1 says it's an MBC3
+    if rom[0x147] == 0x10 && rom.get(0x134..0x13e) == Some(b"TETRIS SET".as_slice()) {
+        return Mapper::M161;
+    }
+
+    match Mapper::try_from(rom[0x147]) {
+        Ok(m) => m,
+        Err(e) => {
+            // MBC5 can address every ROM size, games written for other mappers at least boot
+            let guess = if rom.len() <= 0x8000 {
+                Mapper::RomOnly
+            } else {
+                Mapper::Mbc5
+            };
+            log::warn!("CartridgeHeader: {e}, guessing {guess:?}");
+            guess
+        }
+    }
+}
+
 #[non_exhaustive]
 #[derive(Debug)]
 pub enum Mapper {
@@ -86,11 +311,27 @@ pub enum Mapper {
     Mbc1 = 0x01,
     Mbc1Ram = 0x02,
     Mbc1RamBattery = 0x03,
+    Mmm01 = 0x0b,
+    Mmm01Ram = 0x0c,
+    Mmm01RamBattery = 0x0d,
+    Mbc3TimerBattery = 0x0f,
     Mbc3TimerRamBattery = 0x10,
     Mbc3 = 0x11,
+    Mbc3Ram = 0x12,
     Mbc3RamBattery = 0x13,
     Mbc5 = 0x19,
     Mbc5RamBattery = 0x1b,
+    Mbc5Rumble = 0x1c,
+    Mbc5RumbleRam = 0x1d,
+    Mbc5RumbleRamBattery = 0x1e,
+    Mbc7SensorRumbleRamBattery = 0x22,
+    PocketCamera = 0xfc,
+    HuC3 = 0xfe,
+    HuC1RamBattery = 0xff,
+    // unlicensed mappers without a cartridge type of their own, only found by `detect_mapper`
+    WisdomTree = 0x100,
+    M161 = 0x101,
+    SachenMmc1 = 0x102,
 }
 
 impl TryFrom<u8> for Mapper {
@@ -102,11 +343,23 @@ impl TryFrom<u8> for Mapper {
             0x01 => Ok(Mapper::Mbc1),
             0x02 => Ok(Mapper::Mbc1Ram),
             0x03 => Ok(Mapper::Mbc1RamBattery),
+            0x0b => Ok(Mapper::Mmm01),
+            0x0c => Ok(Mapper::Mmm01Ram),
+            0x0d => Ok(Mapper::Mmm01RamBattery),
+            0x0f => Ok(Mapper::Mbc3TimerBattery),
             0x10 => Ok(Mapper::Mbc3TimerRamBattery),
             0x11 => Ok(Mapper::Mbc3),
+            0x12 => Ok(Mapper::Mbc3Ram),
             0x13 => Ok(Mapper::Mbc3RamBattery),
             0x19 => Ok(Mapper::Mbc5),
             0x1b => Ok(Mapper::Mbc5RamBattery),
+            0x1c => Ok(Mapper::Mbc5Rumble),
+            0x1d => Ok(Mapper::Mbc5RumbleRam),
+            0x1e => Ok(Mapper::Mbc5RumbleRamBattery),
+            0x22 => Ok(Mapper::Mbc7SensorRumbleRamBattery),
+            0xfc => Ok(Mapper::PocketCamera),
+            0xfe => Ok(Mapper::HuC3),
+            0xff => Ok(Mapper::HuC1RamBattery),
             _ => Err(anyhow!("unknown mapper: 0x{value:02x?}")),
         }
     }
@@ -1,6 +1,6 @@
 use anyhow::anyhow;
 
-use crate::core::mbc::Mbc;
+use crate::core::{error::EmuError, mbc::Mbc};
 
 #[derive(Debug)]
 pub struct RomOnly {
@@ -12,23 +12,34 @@ impl TryFrom<Vec<u8>> for RomOnly {
     type Error = anyhow::Error;
 
     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
-        let rom = value[0..=0x7fff].to_vec();
+        let rom = value
+            .get(0..=0x7fff)
+            .ok_or(anyhow!(
+                "NoMapper: ROM should be at least 32 KiB, got {} bytes",
+                value.len()
+            ))?
+            .to_vec();
         let ram = vec![0u8; 0x2000];
         Ok(RomOnly { rom, ram })
     }
 }
 
 impl Mbc for RomOnly {
-    fn read(&self, addr: u16) -> anyhow::Result<u8> {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
         let addr = addr as usize;
         match addr {
             0..=0x7fff => Ok(self.rom[addr]),
             0xa000..=0xbfff => Ok(self.ram[addr - 0xa000]),
-            _ => Err(anyhow!("NoMapper: invalid read: 0x{addr:04x?}")),
+            _ => Err(EmuError::OutOfBounds {
+                device: "NoMapper",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
         }
     }
 
-    fn write(&mut self, addr: u16, val: u8) -> anyhow::Result<()> {
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
         let addr = addr as usize;
         match addr {
             0..=0x7fff => Ok(()), // do nothing
@@ -36,7 +47,12 @@ impl Mbc for RomOnly {
                 self.ram[addr - 0xa000] = val;
                 Ok(())
             }
-            _ => Err(anyhow!("NoMapper: invalid write: 0x{addr:04x?}")),
+            _ => Err(EmuError::OutOfBounds {
+                device: "NoMapper",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
         }
     }
 }
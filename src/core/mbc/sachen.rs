@@ -0,0 +1,81 @@
+use crate::core::{error::EmuError, mbc::Mbc};
+
+/// Sachen's MMC1, an outer base/mask pair picks a window of ROM and an inner bank register
+/// switches within it. The base and mask can only be changed while the selected bank has both
+/// of its top bits set, which is how the multicart menus lock themselves out
+#[derive(Debug)]
+pub struct SachenMmc1 {
+    rom: Vec<Vec<u8>>,
+    base: u8,
+    mask: u8,
+    bank: u8,
+}
+
+impl SachenMmc1 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        let rom = rom.chunks_exact(0x4000).map(|x| x.to_vec()).collect();
+        Self {
+            rom,
+            base: 0,
+            mask: 0,
+            bank: 1,
+        }
+    }
+
+    fn unlocked(&self) -> bool {
+        (self.bank & 0b0011_0000) == 0b0011_0000
+    }
+
+    fn rom(&self, bank: u8, addr: usize) -> u8 {
+        self.rom[bank as usize % self.rom.len()][addr]
+    }
+}
+
+impl Mbc for SachenMmc1 {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3fff => Ok(self.rom(self.base & self.mask, addr)),
+            0x4000..=0x7fff => {
+                let bank = (self.base & self.mask) | (self.bank & !self.mask);
+                Ok(self.rom(bank, addr - 0x4000))
+            }
+            0xa000..=0xbfff => Ok(0xff),
+            _ => Err(EmuError::OutOfBounds {
+                device: "SachenMmc1",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1fff => {
+                if self.unlocked() {
+                    self.base = val;
+                }
+                Ok(())
+            }
+            0x2000..=0x3fff => {
+                self.bank = if val == 0 { 1 } else { val };
+                Ok(())
+            }
+            0x4000..=0x5fff => {
+                if self.unlocked() {
+                    self.mask = val;
+                }
+                Ok(())
+            }
+            0x6000..=0x7fff | 0xa000..=0xbfff => Ok(()),
+            _ => Err(EmuError::OutOfBounds {
+                device: "SachenMmc1",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::core::{error::EmuError, mbc::Mbc};
+
+/// Wisdom Tree's mapper switches the whole 32 KiB ROM window at once, the bank comes from the
+/// low byte of the address written to rather than the value
+#[derive(Debug)]
+pub struct WisdomTree {
+    rom: Vec<Vec<u8>>,
+    bank: usize,
+}
+
+impl WisdomTree {
+    pub fn new(rom: Vec<u8>) -> Self {
+        let rom = rom.chunks(0x8000).map(|x| x.to_vec()).collect();
+        Self { rom, bank: 0 }
+    }
+}
+
+impl Mbc for WisdomTree {
+    fn read(&self, addr: u16) -> Result<u8, EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x7fff => {
+                let bank = &self.rom[self.bank % self.rom.len()];
+                Ok(bank.get(addr).copied().unwrap_or(0xff))
+            }
+            0xa000..=0xbfff => Ok(0xff),
+            _ => Err(EmuError::OutOfBounds {
+                device: "WisdomTree",
+                pc: None,
+                addr: addr as u16,
+                write: false,
+            }),
+        }
+    }
+
+    fn write(&mut self, addr: u16, _val: u8) -> Result<(), EmuError> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3fff => {
+                self.bank = addr & 0xff;
+                log::debug!("WisdomTree: bank {:02x?}", self.bank);
+                Ok(())
+            }
+            0x4000..=0x7fff | 0xa000..=0xbfff => Ok(()),
+            _ => Err(EmuError::OutOfBounds {
+                device: "WisdomTree",
+                pc: None,
+                addr: addr as u16,
+                write: true,
+            }),
+        }
+    }
+}
@@ -1,6 +1,15 @@
+use std::{
+    fmt,
+    hash::{BuildHasher, RandomState},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
 use anyhow::anyhow;
 
-use crate::core::{Buttons, Mode, apu::Apu, mbc::CartridgeHeader};
+use crate::core::{
+    Buttons, Mode, apu::Apu, error::EmuError, mbc::CartridgeHeader, timer::Timer, util::Rng,
+};
 
 // const BOOT: &[u8] = include_bytes!("../../dmg_boot.bin");
 const BOOT: &[u8] = include_bytes!("../../assets/bootix_dmg.bin");
@@ -8,10 +17,8 @@ const BOOT: &[u8] = include_bytes!("../../assets/bootix_dmg.bin");
 #[derive(Default, Debug)]
 pub struct IoRegisters {
     pub joyp: u8,      // 0xff00
+    pub sb: u8,        // 0xff01
     pub sc: u8,        // 0xff02
-    pub tima: u8,      // 0xff05
-    pub tma: u8,       // 0xff06
-    pub tac: u8,       // 0xff07
     pub interrupt: u8, // 0xff0f
     pub lcdc: u8,      // 0xff40
     pub stat: u8,      // 0xff41
@@ -28,6 +35,95 @@ pub struct IoRegisters {
     pub bank: u8,      // 0xff50 - bootrom mapping control
 }
 
+/// What WRAM, VRAM, OAM and HRAM hold at power on. Hardware comes up with noise that differs between
+/// units, some games seed their RNG from it and homebrew reading memory it never wrote only breaks there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RamInit {
+    #[default]
+    Zero,
+    Ones,
+    /// Random bytes from a seed, so a run can be repeated
+    Random(u64),
+    /// Seeded noise shaped like a DMG's, WRAM leans towards set or clear bits in alternating 256-byte stretches
+    Dmg(u64),
+}
+
+impl RamInit {
+    /// A seed that's different every run, the policy records it so the same RAM can be had again
+    pub fn new_seed() -> u64 {
+        // RandomState's keys are fixed on wasm32, so the clock and a counter do the varying there
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        let now = web_time::SystemTime::now()
+            .duration_since(web_time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        Rng::new(RandomState::new().hash_one((now, count))).next_u64()
+    }
+
+    /// The same policy with a different seed, Zero and Ones don't have one
+    pub fn with_seed(self, seed: u64) -> Self {
+        match self {
+            RamInit::Zero | RamInit::Ones => self,
+            RamInit::Random(_) => RamInit::Random(seed),
+            RamInit::Dmg(_) => RamInit::Dmg(seed),
+        }
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        match self {
+            RamInit::Zero | RamInit::Ones => None,
+            RamInit::Random(seed) | RamInit::Dmg(seed) => Some(*seed),
+        }
+    }
+}
+
+impl fmt::Display for RamInit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RamInit::Zero => write!(f, "zero"),
+            RamInit::Ones => write!(f, "ff"),
+            RamInit::Random(seed) => write!(f, "random:{seed:x}"),
+            RamInit::Dmg(seed) => write!(f, "dmg:{seed:x}"),
+        }
+    }
+}
+
+/// `zero`, `ff`, `random[:SEED]` or `dmg[:SEED]` with a hex seed, a new seed is picked if there isn't one
+impl FromStr for RamInit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, seed) = match s.split_once(':') {
+            Some((name, seed)) => (name, Some(seed)),
+            None => (s, None),
+        };
+        let seed = match seed {
+            Some(seed) => u64::from_str_radix(seed, 16)
+                .map_err(|e| anyhow!("RamInit: bad seed {seed:?}: {e}"))?,
+            None => RamInit::new_seed(),
+        };
+        match name {
+            "zero" => Ok(RamInit::Zero),
+            "ff" => Ok(RamInit::Ones),
+            "random" => Ok(RamInit::Random(seed)),
+            "dmg" => Ok(RamInit::Dmg(seed)),
+            _ => Err(anyhow!(
+                "RamInit: unknown policy {name:?}, expected zero, ff, random or dmg"
+            )),
+        }
+    }
+}
+
+/// The kinds of DMG OAM corruption, depending on what the CPU was doing with the OAM address
+#[derive(Debug, Clone, Copy)]
+pub enum OamBug {
+    Write,
+    Read,
+    /// A read with the address being incremented or decremented in the same cycle, like `ld a, [hl+]` or `pop`
+    ReadIncrease,
+}
+
 #[derive(Debug)]
 pub struct Mmu {
     pub ie: u8,
@@ -38,10 +134,23 @@ pub struct Mmu {
     pub io: IoRegisters,
     pub dma_requsted: bool,
     pub buttons: Buttons,
+    /// P10-P13 as last seen, to catch them falling
+    joypad_old: u8,
     pub ppu_mode: Mode,
     pub cartridge: CartridgeHeader,
-    pub sys: u16,
+    pub timer: Timer,
     pub apu: Apu,
+    /// Lock the CPU out of VRAM and OAM while the PPU or DMA is using them, like hardware does, and corrupt OAM
+    /// like a DMG would. Turning it off lets homebrew that gets this wrong still run for debugging
+    pub accurate: bool,
+    /// OAM DMA is copying, OAM reads as 0xff to the CPU
+    pub dma_active: bool,
+    /// Where the running OAM DMA reads from, already moved out of echo RAM
+    dma_source: u16,
+    /// Last byte OAM DMA copied, it's what the CPU sees when it reads the bus DMA is using
+    dma_byte: u8,
+    /// How RAM was filled at power on, including the seed
+    pub ram_init: RamInit,
 }
 
 impl Mmu {
@@ -61,88 +170,212 @@ impl Mmu {
             hram: vec![0; 0x7f],
             dma_requsted: false,
             buttons: Default::default(),
-            ppu_mode: Mode::OamScan,
+            joypad_old: 0b1111,
+            ppu_mode: Mode::HBlank,
             cartridge: header,
-            sys: 0,
+            timer: Timer::default(),
             apu: Apu::new(sample_rate),
+            accurate: true,
+            dma_active: false,
+            dma_source: 0,
+            dma_byte: 0,
+            ram_init: RamInit::Zero,
         };
         Ok(mmu)
     }
 
-    pub fn read(&self, addr: u16) -> anyhow::Result<u8> {
+    /// Fills WRAM, VRAM, OAM and HRAM like the console was just switched on
+    pub fn init_ram(&mut self, init: RamInit) {
+        log::info!("mmu: power-on RAM: {init}");
+        let mut rng = Rng::new(match init {
+            RamInit::Random(seed) | RamInit::Dmg(seed) => seed,
+            RamInit::Zero | RamInit::Ones => 0,
+        });
+        for ram in [
+            &mut self.wram,
+            &mut self.vram,
+            &mut self.oam,
+            &mut self.hram,
+        ] {
+            for (i, byte) in ram.iter_mut().enumerate() {
+                *byte = match init {
+                    RamInit::Zero => 0,
+                    RamInit::Ones => 0xff,
+                    RamInit::Random(_) => rng.next_u8(),
+                    RamInit::Dmg(_) if i & 0x100 == 0 => rng.next_u8() | rng.next_u8(),
+                    RamInit::Dmg(_) => rng.next_u8() & rng.next_u8(),
+                };
+            }
+        }
+        self.ram_init = init;
+    }
+
+    /// VRAM as the PPU sees it, never blocked
+    pub fn vram(&self, addr: u16) -> u8 {
+        self.vram[(addr & 0x1fff) as usize]
+    }
+
+    /// OAM as the PPU sees it, never blocked
+    pub fn oam(&self, addr: u16) -> u8 {
+        self.oam[(addr & 0xff) as usize]
+    }
+
+    /// Points OAM DMA at a page, 0xe0-0xff go to WRAM like echo RAM does
+    pub fn start_dma(&mut self, page: u8) {
+        let src = (page as u16) << 8;
+        self.dma_source = if src >= 0xe000 { src - 0x2000 } else { src };
+    }
+
+    /// OAM DMA reads, the PPU's VRAM lock doesn't stop it
+    pub fn dma_read(&self, offset: u16) -> Result<u8, EmuError> {
+        let addr = self.dma_source + offset;
+        match addr {
+            0x8000..=0x9fff => Ok(self.vram(addr)),
+            0xc000..=0xdfff => Ok(self.wram[(addr - 0xc000) as usize]),
+            _ => self.cartridge.mbc.read(addr),
+        }
+    }
+
+    /// OAM DMA writes straight into OAM, it's the one locking the CPU out
+    pub fn dma_write(&mut self, offset: u16, val: u8) {
+        self.oam[(offset & 0xff) as usize] = val;
+        self.dma_byte = val;
+    }
+
+    /// Word of an OAM row, the corruption formulas are bitwise so byte order doesn't matter
+    fn oam_word(&self, row: usize, word: usize) -> u16 {
+        let i = row * 8 + word * 2;
+        u16::from_le_bytes([self.oam[i], self.oam[i + 1]])
+    }
+
+    fn set_oam_word(&mut self, row: usize, word: usize, val: u16) {
+        let i = row * 8 + word * 2;
+        self.oam[i..i + 2].copy_from_slice(&val.to_le_bytes());
+    }
+
+    fn copy_oam_row(&mut self, from: usize, to: usize, start: usize) {
+        self.oam
+            .copy_within(from * 8 + start..from * 8 + 8, to * 8 + start);
+    }
+
+    /// DMG OAM corruption bug, the row the PPU is scanning gets mangled with the one before it.
+    /// Rows are 8 bytes, the first one (objects 0 and 1) is never affected
+    pub fn oam_bug(&mut self, row: usize, kind: OamBug) {
+        if row == 0 || row >= 20 {
+            return;
+        }
+        if let OamBug::ReadIncrease = kind {
+            // the row before gets corrupted too and then copied around, except near the ends of OAM
+            if (4..19).contains(&row) {
+                let a = self.oam_word(row - 2, 0);
+                let b = self.oam_word(row - 1, 0);
+                let c = self.oam_word(row, 0);
+                let d = self.oam_word(row - 1, 2);
+                self.set_oam_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+                self.copy_oam_row(row - 1, row - 2, 0);
+                self.copy_oam_row(row - 1, row, 0);
+            }
+        }
+
+        let a = self.oam_word(row, 0);
+        let b = self.oam_word(row - 1, 0);
+        let c = self.oam_word(row - 1, 2);
+        let first = match kind {
+            OamBug::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamBug::Read | OamBug::ReadIncrease => b | (a & c),
+        };
+        self.set_oam_word(row, 0, first);
+        self.copy_oam_row(row - 1, row, 2);
+    }
+
+    /// The CPU is trying to use the bus OAM DMA is reading from, either VRAM or everything else below OAM
+    fn dma_conflict(&self, addr: u16) -> bool {
+        let vram_bus = |addr| (0x8000..=0x9fff).contains(&addr);
+        self.accurate
+            && self.dma_active
+            && addr < 0xfe00
+            && vram_bus(addr) == vram_bus(self.dma_source)
+    }
+
+    pub fn lcd_on(&self) -> bool {
+        (self.io.lcdc & 0b1000_0000) > 0
+    }
+
+    fn vram_blocked(&self) -> bool {
+        self.accurate && self.lcd_on() && matches!(self.ppu_mode, Mode::Drawing)
+    }
+
+    fn oam_blocked(&self) -> bool {
+        self.accurate
+            && (self.dma_active
+                || self.lcd_on() && matches!(self.ppu_mode, Mode::OamScan | Mode::Drawing))
+    }
+
+    /// P10-P13, low for held buttons in the groups selected by JOYP bits 4 and 5
+    pub fn joypad_lines(&self) -> u8 {
+        let b = &self.buttons;
+        let mut lines = 0b1111;
+        if self.io.joyp & 0b0001_0000 == 0 {
+            lines &=
+                !((b.down as u8) << 3 | (b.up as u8) << 2 | (b.left as u8) << 1 | b.right as u8);
+        }
+        if self.io.joyp & 0b0010_0000 == 0 {
+            lines &= !((b.start as u8) << 3 | (b.select as u8) << 2 | (b.b as u8) << 1 | b.a as u8);
+        }
+        lines
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        self.update_joypad();
+    }
+
+    /// Requests the joypad interrupt when any of P10-P13 goes from high to low
+    fn update_joypad(&mut self) {
+        let lines = self.joypad_lines();
+        if self.joypad_old & !lines != 0 {
+            log::debug!("mmu: joypad interrupt, lines 0b{lines:04b}");
+            self.io.interrupt |= 0b0001_0000;
+        }
+        self.joypad_old = lines;
+    }
+
+    pub fn read(&self, addr: u16) -> Result<u8, EmuError> {
         log::trace!("read: reading {addr:x?}");
         let a = addr as usize;
         match a {
+            _ if self.dma_conflict(addr) => Ok(self.dma_byte),
             0x0..=0x7fff | 0xa000..=0xbfff => {
                 if (0x0..=0xff).contains(&a) && self.io.bank == 0 {
                     Ok(BOOT[a])
                 } else {
-                    Ok(self.cartridge.mbc.read(addr)?)
+                    self.cartridge.mbc.read(addr)
                 }
             }
+            0x8000..=0x9fff if self.vram_blocked() => Ok(0xff),
             0x8000..=0x9fff => Ok(self.vram[a - 0x8000]),
             0xc000..=0xdfff => Ok(self.wram[a - 0xc000]),
             0xe000..=0xfdff => self.read(addr - 0x2000), // echo ram
+            0xfe00..=0xfe9f if self.oam_blocked() => Ok(0xff),
             0xfe00..=0xfe9f => Ok(self.oam[a - 0xfe00]),
             // 0xfea0..=0xfeff => Err(anyhow!("prohibited read at {a:x?}")),
             0xfea0..=0xfeff => Ok(0xff), // invalid read, just return 0xff
+            // unused bits read as 1
             0xff00..=0xff7f => match a {
-                0xff00 => {
-                    let joyp = self.io.joyp & 0b00110000;
-                    match joyp >> 4 {
-                        0b10 => {
-                            let mut r = 0;
-                            if !self.buttons.down {
-                                r |= 0b1000;
-                            }
-                            if !self.buttons.up {
-                                r |= 0b0100;
-                            }
-                            if !self.buttons.left {
-                                r |= 0b0010;
-                            }
-                            if !self.buttons.right {
-                                r |= 0b0001;
-                            }
-                            Ok(joyp | r)
-                        }
-                        0b01 => {
-                            let mut r = 0;
-                            if !self.buttons.start {
-                                r |= 0b1000;
-                            }
-                            if !self.buttons.select {
-                                r |= 0b0100;
-                            }
-                            if !self.buttons.b {
-                                r |= 0b0010;
-                            }
-                            if !self.buttons.a {
-                                r |= 0b0001;
-                            }
-                            Ok(joyp | r)
-                        }
-                        _ => Ok(joyp | 0b00001111),
-                    }
-                }
-                0xff02 => Ok(self.io.sc),
-                0xff04 => Ok(((self.sys & 0xff00) >> 8) as u8),
-                0xff05 => Ok(self.io.tima),
-                0xff06 => Ok(self.io.tma),
-                0xff07 => Ok(self.io.tac),
-                0xff0f => Ok(self.io.interrupt),
+                0xff00 => Ok(0b1100_0000 | self.io.joyp | self.joypad_lines()),
+                0xff01 => Ok(self.io.sb),
+                0xff02 => Ok(0b0111_1110 | self.io.sc),
+                0xff04 => Ok(self.timer.div()),
+                0xff05 => Ok(self.timer.tima),
+                0xff06 => Ok(self.timer.tma),
+                0xff07 => Ok(0b1111_1000 | self.timer.tac),
+                0xff0f => Ok(0b1110_0000 | self.io.interrupt),
                 0xff10..=0xff14 | 0xff16..=0xff1e | 0xff20..=0xff26 | 0xff30..=0xff3f => {
-                    self.apu.read(addr, self.sys)
+                    self.apu.read(addr, self.timer.sys)
                 }
                 0xff40 => Ok(self.io.lcdc),
-                0xff41 => {
-                    let mut v = self.io.stat;
-                    if self.io.ly == self.io.lyc {
-                        v |= 0b00000100;
-                    }
-                    v |= self.ppu_mode as u8;
-                    Ok(v)
-                }
+                // the PPU keeps the coincidence flag up to date
+                0xff41 => Ok(0b1000_0000 | self.io.stat | self.ppu_mode as u8),
                 0xff42 => Ok(self.io.scy),
                 0xff43 => Ok(self.io.scx),
                 0xff44 => Ok(self.io.ly),
@@ -158,22 +391,28 @@ impl Mmu {
                     log::trace!("FIXME: mmu: sound register read: {a:x?}");
                     Ok(0xff)
                 }
-                _ => {
-                    log::warn!("unimplemented IO reg read at {a:x?}");
-                    Ok(0xff)
-                }
+                // unmapped and CGB-only registers, nothing drives the bus on DMG
+                _ => Ok(0xff),
             },
             0xff80..=0xfffe => Ok(self.hram[a - 0xff80]),
             0xffff => Ok(self.ie),
-            a => Err(anyhow!("read out of bounds at {a:x?}")),
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mmu",
+                pc: None,
+                addr,
+                write: false,
+            }),
         }
     }
 
-    pub fn write(&mut self, addr: u16, val: u8) -> anyhow::Result<()> {
+    pub fn write(&mut self, addr: u16, val: u8) -> Result<(), EmuError> {
         log::trace!("write: writing {val:x?} to {addr:x?}");
         let a = addr as usize;
         match a {
-            0x0..=0x7fff | 0xa000..=0xbfff => Ok(self.cartridge.mbc.write(addr, val)?),
+            _ if self.dma_conflict(addr) => Ok(()),
+            0x0..=0x7fff | 0xa000..=0xbfff => self.cartridge.mbc.write(addr, val),
+            0x8000..=0x9fff if self.vram_blocked() => Ok(()),
+            0xfe00..=0xfe9f if self.oam_blocked() => Ok(()),
             0x8000..=0x9fff => {
                 self.vram[a - 0x8000] = val;
                 Ok(())
@@ -194,37 +433,39 @@ impl Mmu {
             0xff00..=0xff7f => match a {
                 0xff00 => {
                     self.io.joyp = val & 0b00110000;
+                    // selecting a group with a button held pulls its line low too
+                    self.update_joypad();
                     Ok(())
                 }
                 0xff01 => {
                     // TODO: serial, logging for now for blargg
                     // print!("{}", val as char);
+                    self.io.sb = val;
                     Ok(())
                 }
                 0xff02 => {
-                    self.io.sc = val;
+                    self.io.sc = val & 0b1000_0001;
                     Ok(())
                 }
                 0xff04 => {
-                    // any write resets the divider/system clock to 0
-                    self.sys = 0;
+                    self.timer.write_div();
                     Ok(())
                 }
                 0xff05 => {
-                    self.io.tima = val;
+                    self.timer.write_tima(val);
                     Ok(())
                 }
                 0xff06 => {
-                    self.io.tma = val;
+                    self.timer.write_tma(val);
                     Ok(())
                 }
                 0xff07 => {
-                    self.io.tac = val;
+                    self.timer.write_tac(val & 0b0000_0111);
                     Ok(())
                 }
                 0xff0f => {
                     log::debug!("mmu: IF write: 0x{val:x?}");
-                    self.io.interrupt = val;
+                    self.io.interrupt = val & 0b0001_1111;
                     Ok(())
                 }
                 0xff40 => {
@@ -234,7 +475,7 @@ impl Mmu {
                 }
                 0xff41 => {
                     log::debug!("mmu: STAT write: 0x{val:x?}");
-                    self.io.stat = val & 0b01111000;
+                    self.io.stat = (self.io.stat & 0b0000_0100) | (val & 0b0111_1000);
                     Ok(())
                 }
                 0xff42 => {
@@ -288,13 +529,10 @@ impl Mmu {
                     Ok(())
                 }
                 0xff10..=0xff14 | 0xff16..=0xff1e | 0xff20..=0xff26 | 0xff30..=0xff3f => {
-                    self.apu.write(addr, val, self.sys)
-                }
-
-                _ => {
-                    log::warn!("FIXME: mmu: write: unimplemented IO reg write at 0x{a:x?}");
-                    Ok(())
+                    self.apu.write(addr, val, self.timer.sys)
                 }
+                // read-only, unmapped and CGB-only registers
+                _ => Ok(()),
             },
             0xff80..=0xfffe => {
                 self.hram[a - 0xff80] = val;
@@ -305,7 +543,12 @@ impl Mmu {
                 self.ie = val;
                 Ok(())
             }
-            a => Err(anyhow!("mmu: write: write out of bounds at {a:x?}")),
+            _ => Err(EmuError::OutOfBounds {
+                device: "Mmu",
+                pc: None,
+                addr,
+                write: true,
+            }),
         }
     }
 }
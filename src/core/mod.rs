@@ -1,8 +1,11 @@
 pub mod apu;
 pub mod cpu;
+pub mod error;
+pub mod ir;
 pub mod mbc;
 pub mod mmu;
 mod ppu;
+pub mod timer;
 mod util;
 
 #[derive(Debug, Default)]
@@ -1,4 +1,6 @@
-use crate::core::{Mode, mmu::Mmu};
+use std::collections::VecDeque;
+
+use crate::core::{Mode, error::EmuError, mmu::Mmu};
 
 const WIDTH: usize = 160;
 const HEIGHT: usize = 144;
@@ -12,33 +14,90 @@ struct Object {
     attributes: u8,
 }
 
+/// One pixel waiting in the object FIFO, the palette is looked up when it's shifted out
+#[derive(Debug, Clone, Copy, Default)]
+struct ObjPixel {
+    color: u8,
+    obp1: bool,
+    behind_bg: bool,
+}
+
+/// Background/window tile fetcher, every stage takes two dots
+#[derive(Debug, Default)]
+struct Fetcher {
+    /// 0-1 tile number, 2-3 low byte, 4-5 high byte, 6 waiting for the FIFO to empty
+    step: u8,
+    /// Tile column, relative to SCX for the background or to the left edge of the window
+    x: u8,
+    window: bool,
+    tile: u8,
+    low: u8,
+    high: u8,
+}
+
 #[derive(Debug)]
 pub struct Ppu {
     screen: Vec<u8>,
     dot: usize,
+    /// Internal line counter, LY follows it except on line 153 where LY reads 0 early
+    line: u8,
     penalty: usize,
     lx: u8,
     objects: Vec<Object>, // list of objects found during OAM scan
     window_y: u8,
     window_y_update: bool,
     wy_condition: bool,
-    wx_condition: bool,
+    fetcher: Fetcher,
+    bg_fifo: VecDeque<u8>,
+    obj_fifo: VecDeque<ObjPixel>,
+    /// Pixels still to throw away, for SCX fine scroll or a window with WX < 7
+    discard: u8,
+    /// Next object to fetch, they're sorted by X
+    next_obj: usize,
+    /// Dots left on an object fetch, the FIFOs are stalled until it's done
+    obj_fetch: Option<u8>,
+    /// LY value the LYC comparator sees, None for the cycle after LY changes
+    ly_compare: Option<u8>,
+    /// Internal STAT interrupt line, the OR of every enabled source. Only its rising edge requests an interrupt
+    stat_line: bool,
+    /// LCDC bit 7 as of the last dot
+    lcd_on: bool,
+    /// Line 0 right after the LCD is turned on, it skips the OAM scan and stays in mode 0 instead
+    first_line: bool,
+    /// The first frame after the LCD is turned on never makes it to the screen
+    blank: bool,
 }
 pub fn bit(x: u8, i: u8) -> u8 {
     (x & (1 << i)) >> i
 }
+
+fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0b11
+}
+
 impl Ppu {
     pub fn new() -> Self {
         Self {
             dot: 0,
+            line: 0,
             penalty: 0,
-            screen: vec![0; 160 * 144],
+            screen: vec![0; WIDTH * HEIGHT],
             lx: 0,
             objects: Vec::new(),
             window_y: 0,
             window_y_update: false,
             wy_condition: false,
-            wx_condition: false,
+            fetcher: Fetcher::default(),
+            bg_fifo: VecDeque::with_capacity(8),
+            obj_fifo: VecDeque::with_capacity(8),
+            discard: 0,
+            next_obj: 0,
+            obj_fetch: None,
+            ly_compare: Some(0),
+            stat_line: false,
+            lcd_on: false,
+            first_line: false,
+            blank: false,
         }
     }
     // pub fn frame(&mut self, mmu: &mut Mmu) -> anyhow::Result<Vec<u8>> {
@@ -67,7 +126,7 @@ impl Ppu {
     //     Ok(f)
     // }
 
-    pub fn frame(&mut self, mmu: &mut Mmu) -> anyhow::Result<Vec<u8>> {
+    pub fn frame(&mut self, mmu: &mut Mmu) -> Result<Vec<u8>, EmuError> {
         if (mmu.io.lcdc & 0b10000000) != 0 {
             // lcd is enabled
             Ok(self.screen.clone())
@@ -76,163 +135,252 @@ impl Ppu {
         }
     }
 
-    fn draw_bg(&mut self, mmu: &mut Mmu) -> anyhow::Result<u8> {
-        let bg_tilemap_base = if (mmu.io.lcdc & 0b00001000) > 0 {
-            0x9c00
+    /// Row of the BG or window tile being fetched, SCY is read every time so mid-fetch writes land
+    fn fetcher_y(&self, mmu: &Mmu) -> u8 {
+        if self.fetcher.window {
+            self.window_y
         } else {
-            0x9800
-        } as u16;
-
-        let (dx, _) = mmu.io.scx.overflowing_add(self.lx);
-        let (dy, _) = mmu.io.scy.overflowing_add(mmu.io.ly);
-        let tile_x = (dx / 8) as u16;
-        let tile_y = (dy / 8) as u16;
-
-        let tile = mmu.read(bg_tilemap_base + (tile_y * 32 + tile_x))?;
-
-        let tile_base = {
-            if (mmu.io.lcdc & 0b00010000) > 0 {
-                0x8000 + (tile as u16) * 16
-            } else if tile <= 127 {
-                0x9000 + (tile as u16) * 16
-            } else {
-                0x8800 + ((tile - 128) as u16) * 16
-            }
-        };
-        let tile_row = tile_base + 2 * (dy % 8) as u16;
-        let tile_col_d = dx % 8;
-
-        let b1 = mmu.read(tile_row)?;
-        let b1 = bit(b1, 7 - tile_col_d);
-        let b2 = mmu.read(tile_row + 1)?;
-        let b2 = bit(b2, 7 - tile_col_d);
-        let color = (b2 << 1) | b1;
-
-        Ok(color)
+            mmu.io.ly.wrapping_add(mmu.io.scy)
+        }
     }
 
-    fn draw_window(&mut self, mmu: &mut Mmu) -> anyhow::Result<u8> {
-        let window_tilemap_base = if (mmu.io.lcdc & 0b0100_0000) > 0 {
+    fn fetch_tile(&self, mmu: &Mmu) -> u8 {
+        let (map_bit, x) = if self.fetcher.window {
+            (0b0100_0000, self.fetcher.x)
+        } else {
+            (0b0000_1000, (mmu.io.scx / 8).wrapping_add(self.fetcher.x))
+        };
+        let map = if (mmu.io.lcdc & map_bit) > 0 {
             0x9c00
         } else {
             0x9800
         } as u16;
+        let y = self.fetcher_y(mmu);
+        mmu.vram(map + (y / 8) as u16 * 32 + (x % 32) as u16)
+    }
 
-        let (dx, _) = self.lx.overflowing_sub(mmu.io.wx.overflowing_sub(7).0);
-        let dy = self.window_y;
-        let tile_x = (dx / 8) as u16;
-        let tile_y = (dy / 8) as u16;
-
-        let tile = mmu.read(window_tilemap_base + (tile_y * 32 + tile_x))?;
-
-        let tile_base = {
-            if (mmu.io.lcdc & 0b00010000) > 0 {
-                0x8000 + (tile as u16) * 16
-            } else if tile <= 127 {
-                0x9000 + (tile as u16) * 16
-            } else {
-                0x8800 + ((tile - 128) as u16) * 16
-            }
+    fn tile_data_addr(&self, mmu: &Mmu) -> u16 {
+        let tile = self.fetcher.tile;
+        let tile_base = if (mmu.io.lcdc & 0b00010000) > 0 {
+            0x8000 + (tile as u16) * 16
+        } else if tile <= 127 {
+            0x9000 + (tile as u16) * 16
+        } else {
+            0x8800 + ((tile - 128) as u16) * 16
         };
-        let tile_row = tile_base + 2 * (dy % 8) as u16;
-        let tile_col_d = dx % 8;
-
-        let b1 = mmu.read(tile_row)?;
-        let b1 = bit(b1, 7 - tile_col_d);
-        let b2 = mmu.read(tile_row + 1)?;
-        let b2 = bit(b2, 7 - tile_col_d);
-        let color = (b2 << 1) | b1;
+        tile_base + 2 * (self.fetcher_y(mmu) % 8) as u16
+    }
 
-        Ok(color)
+    /// Advances the BG/window fetcher by one dot
+    fn fetcher_clock(&mut self, mmu: &Mmu) {
+        match self.fetcher.step {
+            1 => self.fetcher.tile = self.fetch_tile(mmu),
+            3 => self.fetcher.low = mmu.vram(self.tile_data_addr(mmu)),
+            5 => self.fetcher.high = mmu.vram(self.tile_data_addr(mmu) + 1),
+            _ => {}
+        }
+        if self.fetcher.step < 5 {
+            self.fetcher.step += 1;
+            return;
+        }
+        // the row is only pushed once the FIFO is empty
+        if !self.bg_fifo.is_empty() {
+            self.fetcher.step = 6;
+            return;
+        }
+        for i in (0..8).rev() {
+            let color = (bit(self.fetcher.high, i) << 1) | bit(self.fetcher.low, i);
+            self.bg_fifo.push_back(color);
+        }
+        self.fetcher.x = self.fetcher.x.wrapping_add(1);
+        self.fetcher.step = 0;
     }
 
-    fn draw_objects(&mut self, mmu: &mut Mmu) -> anyhow::Result<Option<(u8, bool, u8)>> {
-        let vram_base = 0x8000_u16;
-        let lx = self.lx + 8;
-        let ly = mmu.io.ly + 16;
+    /// Fetches the next object's row and mixes it into the object FIFO, earlier objects keep their pixels
+    fn fetch_object(&mut self, mmu: &Mmu) {
+        let obj = &self.objects[self.next_obj];
+        self.next_obj += 1;
         let height = if (mmu.io.lcdc & 0b00000100) > 0 {
             16_u8
         } else {
             8_u8
         };
-
-        for obj in &self.objects {
-            if !((obj.x <= lx) && ((obj.x + 8) > lx)) {
-                // object isn't on the column
-                continue;
+        let row = (mmu.io.ly + 16).wrapping_sub(obj.y);
+        let row = if (obj.attributes & 0b01000000) > 0 {
+            (height - 1).wrapping_sub(row)
+        } else {
+            row
+        };
+        // ignore the last bit for tall objects
+        let tile = if height == 16 {
+            obj.tile & 0b1111_1110
+        } else {
+            obj.tile
+        };
+        let tile_row = 0x8000 + (tile as u16) * 16 + 2 * row as u16;
+        let low = mmu.vram(tile_row);
+        let high = mmu.vram(tile_row + 1);
+
+        let pixel = ObjPixel {
+            color: 0,
+            obp1: (obj.attributes & 0b00010000) > 0,
+            behind_bg: (obj.attributes & 0b1000_0000) > 0,
+        };
+        // objects hanging off the left edge lose their first columns
+        let skip = (self.lx as usize + 8).saturating_sub(obj.x as usize);
+        let x_flip = (obj.attributes & 0b00100000) > 0;
+        while self.obj_fifo.len() < 8 {
+            self.obj_fifo.push_back(ObjPixel::default());
+        }
+        for i in skip..8 {
+            let b = if x_flip { i as u8 } else { 7 - i as u8 };
+            let color = (bit(high, b) << 1) | bit(low, b);
+            let slot = &mut self.obj_fifo[i - skip];
+            if slot.color == 0 {
+                *slot = ObjPixel { color, ..pixel };
             }
-            // object is on the current dot
-            let dx = if (obj.attributes & 0b00100000) > 0 {
-                let (x, _) = 7_u8.overflowing_sub(lx - obj.x);
-                x
-            } else {
-                lx - obj.x
-            };
-            let dy = if (obj.attributes & 0b01000000) > 0 {
-                let (y, _) = (height - 1).overflowing_sub(ly - obj.y);
-                y
-            } else {
-                ly - obj.y
-            };
-            // ignore the last bit for tall objects
-            let tile = if height == 16 {
-                obj.tile & 0b1111_1110
+        }
+    }
+
+    /// Sets up mode 3 for the line
+    fn start_drawing(&mut self, mmu: &mut Mmu) {
+        mmu.ppu_mode = Mode::Drawing;
+        if mmu.io.ly == mmu.io.wy {
+            self.wy_condition = true;
+        }
+        self.lx = 0;
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+        self.fetcher = Fetcher::default();
+        self.next_obj = 0;
+        self.obj_fetch = None;
+        self.discard = mmu.io.scx % 8;
+        // the first tile is fetched twice, the first one is thrown away
+        self.penalty = 6;
+    }
+
+    /// One dot of mode 3, returns true once the line is done
+    fn draw(&mut self, mmu: &Mmu) -> bool {
+        if let Some(dots) = self.obj_fetch {
+            // the FIFOs are stalled while the object is fetched
+            if dots > 1 {
+                self.obj_fetch = Some(dots - 1);
             } else {
-                obj.tile
+                self.obj_fetch = None;
+                self.fetch_object(mmu);
+            }
+            return false;
+        }
+
+        if !self.fetcher.window
+            && (mmu.io.lcdc & 0b0010_0000) > 0
+            && self.wy_condition
+            && self.lx as u16 + 7 >= mmu.io.wx as u16
+        {
+            // window starts here, throw the BG away and fetch from the window's left edge
+            self.bg_fifo.clear();
+            self.fetcher = Fetcher {
+                window: true,
+                ..Default::default()
             };
+            self.discard = 7_u8.saturating_sub(mmu.io.wx);
+            self.window_y_update = true;
+        }
 
-            // TODO: this is mostly the same logic as draw_bg, extract this to a function
-            let tile_base = vram_base + (tile as u16) * 16;
-            let tile_row = tile_base + 2 * dy as u16;
-            let b1 = mmu.read(tile_row)?;
-            let b1 = bit(b1, 7 - dx);
-            let b2 = mmu.read(tile_row + 1)?;
-            let b2 = bit(b2, 7 - dx);
-            let color = (b2 << 1) | b1;
-
-            if color == 0b00 {
-                continue;
-            }
-            let palette = if (obj.attributes & 0b00010000) > 0 {
-                mmu.io.obp1
+        if !self.bg_fifo.is_empty()
+            && self
+                .objects
+                .get(self.next_obj)
+                .is_some_and(|obj| obj.x as u16 <= self.lx as u16 + 8)
+        {
+            if (mmu.io.lcdc & 0b0000_0010) == 0 {
+                // objects are off, nothing gets fetched
+                self.next_obj += 1;
+            } else if self.fetcher.step >= 5 {
+                // 6 dots, counting this one
+                self.obj_fetch = Some(5);
+                return false;
             } else {
-                mmu.io.obp0
-            };
+                // the BG fetch in progress has to finish first
+                self.fetcher_clock(mmu);
+                return false;
+            }
+        }
 
-            return Ok(Some((color, (obj.attributes & 0b1000_0000) > 0, palette)));
+        if let Some(bg) = self.bg_fifo.pop_front() {
+            if self.discard > 0 {
+                self.discard -= 1;
+            } else {
+                let obj = self.obj_fifo.pop_front().unwrap_or_default();
+                let bg = if (mmu.io.lcdc & 0b0000_0001) > 0 {
+                    bg
+                } else {
+                    // BG and window disabled
+                    0
+                };
+                let color = if obj.color != 0
+                    && (mmu.io.lcdc & 0b0000_0010) > 0
+                    && !(obj.behind_bg && bg != 0)
+                {
+                    shade(if obj.obp1 { mmu.io.obp1 } else { mmu.io.obp0 }, obj.color)
+                } else {
+                    shade(mmu.io.bgp, bg)
+                };
+                if !self.blank {
+                    self.screen[mmu.io.ly as usize * WIDTH + self.lx as usize] = color;
+                }
+                self.lx += 1;
+                if self.lx == 160 {
+                    return true;
+                }
+            }
         }
-        Ok(None)
+
+        self.fetcher_clock(mmu);
+        false
     }
 
-    pub fn clock(&mut self, mmu: &mut Mmu) -> anyhow::Result<()> {
+    pub fn clock(&mut self, mmu: &mut Mmu) -> Result<(), EmuError> {
         // println!(
         // "clock: dot: {} lx: {} ly: {} mode: {:?}",
         // self.dot, self.lx, mmu.io.ly, self.mode
         // );
+        if mmu.lcd_on() != self.lcd_on {
+            self.lcd_on = !self.lcd_on;
+            if self.lcd_on {
+                self.turn_on(mmu);
+            } else {
+                self.turn_off(mmu);
+            }
+        }
+        if !self.lcd_on {
+            return Ok(());
+        }
+
         if self.penalty > 0 {
             self.penalty -= 1;
             self.dot += 1;
+            self.update_stat(mmu);
             return Ok(());
         }
 
         match mmu.ppu_mode {
+            Mode::HBlank if self.first_line => {
+                if self.dot == 79 {
+                    self.first_line = false;
+                    self.objects.clear();
+                    self.start_drawing(mmu);
+                }
+            }
             Mode::HBlank => {
                 if self.dot == 455 {
                     if mmu.io.ly == 143 {
                         mmu.ppu_mode = Mode::VBlank;
                         mmu.io.interrupt |= 0b00000001; // request vblank interrupt
                         self.window_y = 0;
-                        if (mmu.io.stat & 0b0001_0000) > 0 {
-                            // raise STAT interrupt for mode 1
-                            mmu.io.interrupt |= 0b0000_0010;
-                        }
+                        self.blank = false;
                     } else {
                         mmu.ppu_mode = Mode::OamScan;
-                        self.wx_condition = false;
-                        if (mmu.io.stat & 0b0010_0000) > 0 {
-                            // raise STAT interrupt for mode 2
-                            mmu.io.interrupt |= 0b0000_0010;
-                        }
                     }
                 }
             }
@@ -244,7 +392,7 @@ impl Ppu {
                         if self.objects.len() == 10 {
                             break;
                         }
-                        let y = mmu.read(OAM_BASE + i * 4)?;
+                        let y = mmu.oam(OAM_BASE + i * 4);
                         let ly = mmu.io.ly + 16; // object y pos is offset by 16
 
                         let height = if (mmu.io.lcdc & 0b00000100) != 0 {
@@ -262,9 +410,9 @@ impl Ppu {
                         // object is on the line, we should draw it
                         let obj = Object {
                             y,
-                            x: mmu.read(OAM_BASE + i * 4 + 1)?,
-                            tile: mmu.read(OAM_BASE + i * 4 + 2)?,
-                            attributes: mmu.read(OAM_BASE + i * 4 + 3)?,
+                            x: mmu.oam(OAM_BASE + i * 4 + 1),
+                            tile: mmu.oam(OAM_BASE + i * 4 + 2),
+                            attributes: mmu.oam(OAM_BASE + i * 4 + 3),
                         };
                         self.objects.push(obj);
                     }
@@ -272,96 +420,25 @@ impl Ppu {
                 }
 
                 if self.dot == 79 {
-                    mmu.ppu_mode = Mode::Drawing;
-                    // self.penalty = 12 + (mmu.io.scx % 8) as usize;
-                    if mmu.io.ly == mmu.io.wy {
-                        self.wy_condition = true;
-                    }
+                    self.start_drawing(mmu);
                 }
             }
             Mode::Drawing => {
-                if self.lx + 7 >= mmu.io.wx {
-                    self.wx_condition = true;
-                }
-                let mut bg_dot = None;
-                let mut window_dot = None;
-                let mut object_dot = None;
-                let screen_idx = mmu.io.ly as usize * WIDTH + self.lx as usize;
-
-                if (mmu.io.lcdc & 0b0000_0001) > 0 {
-                    // BG enabled
-                    bg_dot = Some(self.draw_bg(mmu)?);
-
-                    // window is only drawn if BG is enabled
-                    if (mmu.io.lcdc & 0b0010_0000) > 0 && self.wy_condition && self.wx_condition {
-                        self.window_y_update = true;
-                        window_dot = Some(self.draw_window(mmu)?);
-                    }
-                } else {
-                    // BG disabled, set to white
-                    self.screen[screen_idx] = mmu.io.bgp & 0b00000011;
-                }
-
-                if (mmu.io.lcdc & 0b0000_0010) > 0 {
-                    object_dot = self.draw_objects(mmu)?;
-                }
-
-                let bg_idx = window_dot.unwrap_or(bg_dot.unwrap_or(0));
-
-                let obj_color = if let Some((dot, priority, palette)) = object_dot {
-                    if priority && bg_idx != 0 {
-                        None
-                    } else {
-                        Some(match dot {
-                            0b00 => palette & 0b00000011,
-                            0b01 => (palette & 0b00001100) >> 2,
-                            0b10 => (palette & 0b00110000) >> 4,
-                            0b11 => (palette & 0b11000000) >> 6,
-                            _ => unreachable!("ppu: draw_objects: invalid color {dot:02x?}"),
-                        })
-                    }
-                } else {
-                    None
-                };
-
-                let color = if let Some(color) = obj_color {
-                    color
-                } else {
-                    match bg_idx {
-                        0b00 => mmu.io.bgp & 0b00000011,
-                        0b01 => (mmu.io.bgp & 0b00001100) >> 2,
-                        0b10 => (mmu.io.bgp & 0b00110000) >> 4,
-                        0b11 => (mmu.io.bgp & 0b11000000) >> 6,
-                        _ => unreachable!("ppu: draw_window: invalid color {bg_idx:02x?}"),
-                    }
-                };
-
-                self.screen[screen_idx] = color;
-
-                self.lx += 1;
-                if self.lx == 160 {
+                if self.draw(mmu) {
                     self.lx = 0;
                     mmu.ppu_mode = Mode::HBlank;
                     if self.window_y_update {
                         self.window_y += 1;
                         self.window_y_update = false;
                     }
-                    if (mmu.io.stat & 0b0000_1000) > 0 {
-                        // raise STAT interrupt for mode 0
-                        mmu.io.interrupt |= 0b0000_0010;
-                    }
-                };
+                }
             }
             Mode::VBlank => {
-                if self.dot == 455 && mmu.io.ly == 153 {
+                if self.dot == 455 && self.line == 153 {
                     // end of frame
                     mmu.ppu_mode = Mode::OamScan;
                     self.wy_condition = false;
                     self.window_y = 0;
-                    if (mmu.io.stat & 0b0010_0000) > 0 {
-                        // raise STAT interrupt for mode 2
-                        mmu.io.interrupt |= 0b0000_0010;
-                    }
                 }
             }
         }
@@ -370,23 +447,92 @@ impl Ppu {
         if self.dot == 456 {
             // overflow
             self.dot = 0;
-            mmu.io.ly += 1;
-            if mmu.io.ly == 154 {
-                // overflow
-                mmu.io.ly = 0;
+            self.line = (self.line + 1) % 154;
+            if mmu.io.ly != self.line {
+                // the comparator doesn't see the new LY until the next cycle
+                self.ly_compare = None;
             }
+            mmu.io.ly = self.line;
+        }
 
-            // log::debug!("{} {} {}", mmu.io.stat, mmu.io.ly, mmu.io.lyc);
-            if (mmu.io.stat & 0b0100_0000) > 0 && mmu.io.ly == mmu.io.lyc {
-                log::debug!("lyc interrupt");
-                mmu.io.interrupt |= 0b0000_0010;
+        // line 153 only reads as 153 for one cycle, then LY=LYC matches against 0 for the rest of it
+        match (self.line, self.dot) {
+            (153, 4) => {
+                self.ly_compare = Some(153);
+                mmu.io.ly = 0;
             }
+            (153, 8) => self.ly_compare = None,
+            (153, 12) => self.ly_compare = Some(0),
+            (_, 4) => self.ly_compare = Some(mmu.io.ly),
+            _ => {}
         }
+        self.update_stat(mmu);
 
         Ok(())
     }
 
-    pub fn dump_vram(&mut self, mmu: &mut Mmu) -> anyhow::Result<Vec<u8>> {
+    /// OAM row the PPU is reading this M-cycle, only during mode 2 where it scans one row per M-cycle
+    pub fn oam_row(&self, mmu: &Mmu) -> Option<usize> {
+        match mmu.ppu_mode {
+            Mode::OamScan if self.lcd_on => Some(self.dot / 4),
+            _ => None,
+        }
+    }
+
+    /// LY goes back to 0 and the PPU sits in mode 0 until the LCD is turned back on
+    fn turn_off(&mut self, mmu: &mut Mmu) {
+        if !matches!(mmu.ppu_mode, Mode::VBlank) {
+            // real hardware can get damaged by this, emulators don't care but people testing on one should
+            log::warn!("ppu: LCD disabled outside of VBlank at LY {}", mmu.io.ly);
+        }
+        self.dot = 0;
+        self.line = 0;
+        self.penalty = 0;
+        self.lx = 0;
+        self.window_y = 0;
+        self.window_y_update = false;
+        self.wy_condition = false;
+        mmu.io.ly = 0;
+        mmu.ppu_mode = Mode::HBlank;
+    }
+
+    /// Line 0 after turning on is 4 dots short and starts in mode 0, the frame stays blank
+    fn turn_on(&mut self, mmu: &mut Mmu) {
+        self.dot = 4;
+        self.first_line = true;
+        self.blank = true;
+        self.screen.fill(0);
+        self.ly_compare = Some(0);
+        self.stat_line = false;
+        mmu.ppu_mode = Mode::HBlank;
+    }
+
+    /// Updates the coincidence flag and the STAT interrupt line, requesting an interrupt on a rising edge
+    fn update_stat(&mut self, mmu: &mut Mmu) {
+        let stat = mmu.io.stat;
+        let coincidence = self.ly_compare == Some(mmu.io.lyc);
+        if coincidence {
+            mmu.io.stat |= 0b0000_0100;
+        } else {
+            mmu.io.stat &= !0b0000_0100;
+        }
+
+        let mode = match mmu.ppu_mode {
+            Mode::HBlank => stat & 0b0000_1000,
+            // the mode 2 source also fires at the start of VBlank
+            Mode::VBlank if self.line == 144 && self.dot == 0 => stat & 0b0011_0000,
+            Mode::VBlank => stat & 0b0001_0000,
+            Mode::OamScan => stat & 0b0010_0000,
+            Mode::Drawing => 0,
+        };
+        let line = mode > 0 || (coincidence && (stat & 0b0100_0000) > 0);
+        if line && !self.stat_line {
+            mmu.io.interrupt |= 0b0000_0010;
+        }
+        self.stat_line = line;
+    }
+
+    pub fn dump_vram(&mut self, mmu: &mut Mmu) -> Result<Vec<u8>, EmuError> {
         let mut out = vec![0; 32768];
         let base = 0x8000;
 
@@ -398,8 +544,8 @@ impl Ppu {
                     for y in 0..8 {
                         let screen_base = bank * 16 * 8 * 64 + (tile_y * 8 + y) * 128 + tile_x * 8;
                         let vram_base = (tile_y * 16 * 16) + (tile_x * 16) + y * 2;
-                        let b1 = mmu.read(bank_base + vram_base)?;
-                        let b2 = mmu.read(bank_base + vram_base + 1)?;
+                        let b1 = mmu.vram(bank_base + vram_base);
+                        let b2 = mmu.vram(bank_base + vram_base + 1);
                         for x in 0..=7 {
                             let bit1 = bit(b1, 7 - x);
                             let bit2 = bit(b2, 7 - x) << 1;
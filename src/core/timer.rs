@@ -0,0 +1,215 @@
+/// Bit of the system counter that clocks DIV-APU, bit 4 of DIV
+const DIV_APU_BIT: u16 = 0b0001_0000_0000_0000;
+
+/// DIV, TIMA, TMA and TAC. Everything here is driven by falling edges of the 16-bit system counter, so
+/// resetting DIV or changing TAC can tick TIMA (and DIV-APU) just like the counter rolling over does
+#[derive(Debug, Default)]
+pub struct Timer {
+    /// System counter, DIV is the upper 8 bits
+    pub sys: u16,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+    /// TIMA overflowed, it reads 0 until TMA is reloaded on the next M-cycle
+    overflow: bool,
+    /// TMA was reloaded this M-cycle, TIMA writes are ignored and TMA writes go through to TIMA
+    reloading: bool,
+    /// Bit 12 fell since the APU last looked
+    div_apu: bool,
+}
+
+fn falling_edge(old: u16, new: u16, mask: u16) -> bool {
+    (old & mask) != 0 && (new & mask) == 0
+}
+
+impl Timer {
+    /// System counter bit TAC selects
+    fn mask(&self) -> u16 {
+        match self.tac & 0b11 {
+            0b00 => 1 << 9,
+            0b01 => 1 << 3,
+            0b10 => 1 << 5,
+            0b11 => 1 << 7,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The timer's input, the selected bit ANDed with the enable bit
+    fn input(&self, sys: u16) -> bool {
+        (self.tac & 0b100) > 0 && (sys & self.mask()) > 0
+    }
+
+    fn increment(&mut self) {
+        let (val, overflow) = self.tima.overflowing_add(1);
+        self.tima = val;
+        if overflow {
+            log::debug!("timer: overflow");
+            self.overflow = true;
+        }
+    }
+
+    /// Moves the system counter, ticking TIMA and DIV-APU on falling edges
+    fn set_sys(&mut self, sys: u16) {
+        let old = self.sys;
+        let old_input = self.input(old);
+        self.sys = sys;
+        if old_input && !self.input(sys) {
+            self.increment();
+        }
+        if falling_edge(old, sys, DIV_APU_BIT) {
+            self.div_apu = true;
+        }
+    }
+
+    /// Start of an M-cycle, returns true if the timer interrupt should be requested
+    pub fn m_cycle(&mut self) -> bool {
+        self.reloading = false;
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            return true;
+        }
+        false
+    }
+
+    /// Advances the system counter by one T-cycle
+    pub fn clock(&mut self) {
+        self.set_sys(self.sys.wrapping_add(1));
+    }
+
+    /// Whether DIV-APU should tick, clears the edge
+    pub fn div_apu_edge(&mut self) -> bool {
+        std::mem::take(&mut self.div_apu)
+    }
+
+    pub fn div(&self) -> u8 {
+        (self.sys >> 8) as u8
+    }
+
+    /// Any write resets the whole system counter
+    pub fn write_div(&mut self) {
+        self.set_sys(0);
+    }
+
+    pub fn write_tima(&mut self, val: u8) {
+        if self.reloading {
+            return;
+        }
+        // writing during the overflow cycle cancels the reload and the interrupt
+        self.overflow = false;
+        self.tima = val;
+    }
+
+    pub fn write_tma(&mut self, val: u8) {
+        self.tma = val;
+        if self.reloading {
+            self.tima = val;
+        }
+    }
+
+    pub fn write_tac(&mut self, val: u8) {
+        let old_input = self.input(self.sys);
+        self.tac = val;
+        if old_input && !self.input(self.sys) {
+            self.increment();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Enabled, selecting bit 3 so TIMA ticks every 16 T-cycles
+    fn timer() -> Timer {
+        Timer {
+            tac: 0b101,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ticks_at_the_selected_rate() {
+        let mut timer = timer();
+        for _ in 0..16 * 5 {
+            timer.clock();
+        }
+        assert_eq!(timer.tima, 5);
+
+        timer.tac = 0b001;
+        for _ in 0..16 * 5 {
+            timer.clock();
+        }
+        assert_eq!(timer.tima, 5);
+    }
+
+    #[test]
+    fn div_write_ticks_on_falling_edge() {
+        let mut timer = timer();
+        timer.sys = 0b1000;
+        timer.write_div();
+        assert_eq!((timer.sys, timer.tima), (0, 1));
+
+        // the selected bit was already low, nothing falls
+        timer.sys = 0b0111;
+        timer.write_div();
+        assert_eq!(timer.tima, 1);
+    }
+
+    #[test]
+    fn tac_write_ticks_on_falling_edge() {
+        let mut timer = timer();
+        timer.sys = 0b1000;
+        // disabling pulls the input low
+        timer.write_tac(0b001);
+        assert_eq!(timer.tima, 1);
+        // and so does selecting a bit that's clear
+        timer.write_tac(0b101);
+        timer.write_tac(0b110);
+        assert_eq!(timer.tima, 2);
+    }
+
+    #[test]
+    fn overflow_reloads_a_cycle_later() {
+        let mut timer = timer();
+        timer.tima = 0xff;
+        timer.tma = 0x42;
+        timer.sys = 0b1111;
+        timer.clock();
+        assert_eq!(timer.tima, 0);
+        assert!(timer.m_cycle());
+        assert_eq!(timer.tima, 0x42);
+
+        // TIMA writes are ignored while reloading, TMA writes go straight through
+        timer.write_tima(0x10);
+        assert_eq!(timer.tima, 0x42);
+        timer.write_tma(0x20);
+        assert_eq!(timer.tima, 0x20);
+        assert!(!timer.m_cycle());
+    }
+
+    #[test]
+    fn tima_write_cancels_overflow() {
+        let mut timer = timer();
+        timer.tima = 0xff;
+        timer.sys = 0b1111;
+        timer.clock();
+        timer.write_tima(0x10);
+        assert!(!timer.m_cycle());
+        assert_eq!(timer.tima, 0x10);
+    }
+
+    #[test]
+    fn div_apu_on_bit_12_falling() {
+        let mut timer = timer();
+        timer.sys = 0x1fff;
+        timer.clock();
+        assert!(timer.div_apu_edge());
+        assert!(!timer.div_apu_edge());
+
+        timer.sys = 0x1234;
+        timer.write_div();
+        assert!(timer.div_apu_edge());
+    }
+}
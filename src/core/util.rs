@@ -3,3 +3,25 @@ use num_traits::PrimInt;
 pub fn extract<T: PrimInt>(val: T, mask: T) -> T {
     (val & mask) >> mask.trailing_zeros() as usize
 }
+
+/// splitmix64, plenty for filling RAM with noise. The same seed always gives the same bytes
+#[derive(Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.next_u64() as u8
+    }
+}
@@ -1,6 +1,8 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
-mod core;
+pub mod archive;
+pub mod core;
+pub mod patch;
 mod screen;
-pub use app::GbApp;
+pub use app::{GbApp, PickedFile};
@@ -1,11 +1,377 @@
 #![warn(clippy::all, rust_2018_idioms)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+// Exit codes, so scripts can tell why we stopped
+#[cfg(not(target_arch = "wasm32"))]
+const EXIT_IO: u8 = 1;
+#[cfg(not(target_arch = "wasm32"))]
+const EXIT_BAD_ROM: u8 = 2;
+#[cfg(not(target_arch = "wasm32"))]
+const EXIT_EMULATION: u8 = 3;
+
 // When compiling natively:
 #[cfg(not(target_arch = "wasm32"))]
-fn main() -> eframe::Result {
+fn main() -> std::process::ExitCode {
+    use std::process::ExitCode;
+
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
 
+    // usage: gbrs [--frames N [--camera IMAGE]... [--ir-link ROM] | --info] [--patch PATCH]... [--ram-init POLICY] [ROM]
+    let mut frames = None;
+    let mut info = false;
+    let mut ram_init = gbrs::core::mmu::RamInit::default();
+    let mut patch_paths = Vec::new();
+    let mut camera_paths = Vec::new();
+    let mut ir_link = None;
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => match args.next().and_then(|x| x.parse::<usize>().ok()) {
+                Some(n) => frames = Some(n),
+                None => {
+                    eprintln!("--frames needs a number of frames to run");
+                    return ExitCode::from(EXIT_IO);
+                }
+            },
+            "--info" => info = true,
+            "--ram-init" => match args.next().map(|x| x.parse()) {
+                Some(Ok(init)) => ram_init = init,
+                Some(Err(e)) => {
+                    eprintln!("--ram-init: {e}");
+                    return ExitCode::from(EXIT_IO);
+                }
+                None => {
+                    eprintln!("--ram-init needs zero, ff, random[:SEED] or dmg[:SEED]");
+                    return ExitCode::from(EXIT_IO);
+                }
+            },
+            "--patch" => match args.next() {
+                Some(path) => patch_paths.push(std::path::PathBuf::from(path)),
+                None => {
+                    eprintln!("--patch needs a patch file");
+                    return ExitCode::from(EXIT_IO);
+                }
+            },
+            "--ir-link" => match args.next() {
+                Some(path) => ir_link = Some(std::path::PathBuf::from(path)),
+                None => {
+                    eprintln!("--ir-link needs a second ROM to link to");
+                    return ExitCode::from(EXIT_IO);
+                }
+            },
+            "--camera" => match args.next() {
+                Some(path) => camera_paths.push(path),
+                None => {
+                    eprintln!("--camera needs a PNG or JPEG image");
+                    return ExitCode::from(EXIT_IO);
+                }
+            },
+            _ => path = Some(arg),
+        }
+    }
+
+    if !camera_paths.is_empty() && frames.is_none() {
+        eprintln!(
+            "--camera only works with --frames, use File -> Load Camera Images in the window"
+        );
+        return ExitCode::from(EXIT_IO);
+    }
+    if ir_link.is_some() && frames.is_none() {
+        // a second process can't keep IR timing, both games have to run side by side in this one
+        eprintln!("--ir-link only works with --frames, the window only has Debug -> IR loopback");
+        return ExitCode::from(EXIT_IO);
+    }
+    // the sensor steps through these one capture at a time, like the window's camera images
+    let mut camera_frames = Vec::new();
+    for path in &camera_paths {
+        let frame = std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|image| gbrs::core::mbc::camera::decode_frame(&image));
+        match frame {
+            Ok(frame) => camera_frames.push(frame),
+            Err(e) => {
+                eprintln!("couldn't load camera image {path}: {e}");
+                return ExitCode::from(EXIT_IO);
+            }
+        }
+    }
+
+    // a patch with the same name as the ROM is picked up automatically
+    if let Some(path) = &path {
+        let path = std::path::Path::new(path);
+        for ext in ["ips", "ups", "bps"] {
+            let sibling = path.with_extension(ext);
+            if sibling.is_file() && !patch_paths.contains(&sibling) {
+                eprintln!("applying {}", sibling.display());
+                patch_paths.push(sibling);
+            }
+        }
+    }
+
+    // and so is a battery save
+    let rom_path = path.as_ref().map(std::path::PathBuf::from);
+    let save_path = rom_path.as_ref().map(|path| path.with_extension("sav"));
+
+    let file = match path.map(std::fs::read).transpose() {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("couldn't read ROM: {e}");
+            return ExitCode::from(EXIT_IO);
+        }
+    };
+    let patches: Vec<Vec<u8>> = match patch_paths.iter().map(std::fs::read).collect() {
+        Ok(patches) => patches,
+        Err(e) => {
+            eprintln!("couldn't read patch: {e}");
+            return ExitCode::from(EXIT_IO);
+        }
+    };
+    // --info and --frames use the first ROM in an archive, the window asks which one to run
+    let rom = file
+        .clone()
+        .map(|file| {
+            let rom = gbrs::archive::roms(file)?.remove(0).rom;
+            patches
+                .iter()
+                .try_fold(rom, |rom, patch| gbrs::patch::apply(&rom, patch))
+        })
+        .transpose();
+    let rom = match rom {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("invalid ROM: {e:#}");
+            return ExitCode::from(EXIT_BAD_ROM);
+        }
+    };
+
+    if info {
+        let Some(rom) = rom else {
+            eprintln!("--info needs a ROM to look at");
+            return ExitCode::from(EXIT_IO);
+        };
+        // no mapper is built, so headers the loader rejects still get a report
+        if rom.len() < 0x150 {
+            eprintln!(
+                "invalid ROM: too small to have a header ({} bytes)",
+                rom.len()
+            );
+            return ExitCode::from(EXIT_BAD_ROM);
+        }
+        match serde_json::to_string_pretty(&gbrs::core::mbc::info::RomInfo::new(&rom)) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                eprintln!("couldn't write ROM info: {e}");
+                return ExitCode::from(EXIT_IO);
+            }
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    match (frames, rom) {
+        (Some(frames), Some(rom)) => {
+            // the linked game gets the first ROM in its archive too, but no patches
+            let ir_peer = match ir_link.map(|path| (std::fs::read(&path), path)) {
+                Some((Ok(file), path)) => match gbrs::archive::roms(file) {
+                    Ok(mut roms) => Some((roms.remove(0).rom, path)),
+                    Err(e) => {
+                        eprintln!("invalid ROM: {e:#}");
+                        return ExitCode::from(EXIT_BAD_ROM);
+                    }
+                },
+                Some((Err(e), _)) => {
+                    eprintln!("couldn't read ROM: {e}");
+                    return ExitCode::from(EXIT_IO);
+                }
+                None => None,
+            };
+            headless(rom, frames, ram_init, save_path, camera_frames, ir_peer)
+        }
+        (Some(_), None) => {
+            eprintln!("--frames needs a ROM to run");
+            ExitCode::from(EXIT_IO)
+        }
+        (None, _) => match window(
+            file.map(|file| {
+                let patches = patches
+                    .into_iter()
+                    .zip(patch_paths)
+                    .map(|(p, path)| (p, Some(path)));
+                [vec![(file, rom_path)], patches.collect()].concat()
+            }),
+            ram_init,
+        ) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{e}");
+                ExitCode::from(EXIT_IO)
+            }
+        },
+    }
+}
+
+/// Runs without a window or audio, for scripts and CI
+#[cfg(not(target_arch = "wasm32"))]
+fn headless(
+    rom: Vec<u8>,
+    frames: usize,
+    ram_init: gbrs::core::mmu::RamInit,
+    save_path: Option<std::path::PathBuf>,
+    camera_frames: Vec<Vec<u8>>,
+    ir_peer: Option<(Vec<u8>, std::path::PathBuf)>,
+) -> std::process::ExitCode {
+    use std::process::ExitCode;
+
+    if ram_init != Default::default() {
+        // so a run that went wrong can be repeated with the same RAM
+        eprintln!("power-on RAM: {ram_init}");
+    }
+    let mut game = match Game::start(rom, ram_init, save_path, "") {
+        Ok(game) => game,
+        Err(code) => return code,
+    };
+    if !camera_frames.is_empty() {
+        if !matches!(
+            game.cpu.mmu.cartridge.cartridge_type,
+            gbrs::core::mbc::Mapper::PocketCamera
+        ) {
+            eprintln!("--camera needs a Pocket Camera cart");
+            return ExitCode::from(EXIT_IO);
+        }
+        game.cpu.mmu.cartridge.mbc.set_camera_frames(camera_frames);
+    }
+    let mut peer = match ir_peer {
+        Some((rom, path)) => {
+            // two copies of the same game can't share a save
+            let mut peer_save = path.with_extension("sav");
+            if Some(&peer_save) == game.save_path.as_ref() {
+                peer_save = path.with_extension("ir.sav");
+            }
+            match Game::start(rom, ram_init, Some(peer_save), "IR peer: ") {
+                Ok(peer) => Some(peer),
+                Err(code) => return code,
+            }
+        }
+        None => None,
+    };
+    if let Some(peer) = &mut peer {
+        let (a, b) = gbrs::core::ir::IrLink::pair();
+        game.cpu.mmu.cartridge.mbc.connect_ir(a);
+        peer.cpu.mmu.cartridge.mbc.connect_ir(b);
+    }
+
+    for _ in 0..frames {
+        let result = match &mut peer {
+            Some(peer) => game.cpu.frame_linked(&mut peer.cpu),
+            None => game.cpu.frame(),
+        };
+        if let Err(e) = result {
+            eprintln!("emulation error: {e:#}");
+            eprintln!("{}", game.cpu.state());
+            if let Some(peer) = &peer {
+                eprintln!("IR peer: {}", peer.cpu.state());
+            }
+            return ExitCode::from(EXIT_EMULATION);
+        }
+        game.drain();
+        if let Some(peer) = &mut peer {
+            peer.drain();
+        }
+    }
+
+    for game in std::iter::once(&game).chain(&peer) {
+        if let Err(code) = game.finish() {
+            return code;
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// A headless game and the battery save next to its ROM
+#[cfg(not(target_arch = "wasm32"))]
+struct Game {
+    cpu: gbrs::core::cpu::Cpu,
+    save_path: Option<std::path::PathBuf>,
+    /// The save as it was loaded, it's only written back if the game changed it
+    loaded: Option<Vec<u8>>,
+    /// Goes in front of messages so the IR peer's can be told apart
+    label: &'static str,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Game {
+    fn start(
+        rom: Vec<u8>,
+        ram_init: gbrs::core::mmu::RamInit,
+        save_path: Option<std::path::PathBuf>,
+        label: &'static str,
+    ) -> Result<Self, std::process::ExitCode> {
+        use std::process::ExitCode;
+
+        let mut cpu = match gbrs::core::cpu::Cpu::new(rom, 48000) {
+            Ok(cpu) => cpu,
+            Err(e) => {
+                eprintln!("{label}invalid ROM: {e:#}");
+                return Err(ExitCode::from(EXIT_BAD_ROM));
+            }
+        };
+        cpu.mmu.init_ram(ram_init);
+        // battery saves live next to the ROM, same as in the window
+        if let (Some(path), Some(_)) = (&save_path, cpu.mmu.cartridge.mbc.save()) {
+            match std::fs::read(path) {
+                Ok(save) => {
+                    if let Err(e) = cpu.mmu.cartridge.mbc.load_save(&save) {
+                        eprintln!("{label}couldn't load save {}: {e}", path.display());
+                        return Err(ExitCode::from(EXIT_IO));
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    eprintln!("{label}couldn't read save {}: {e}", path.display());
+                    return Err(ExitCode::from(EXIT_IO));
+                }
+            }
+        }
+        Ok(Self {
+            loaded: cpu.mmu.cartridge.mbc.save(),
+            cpu,
+            save_path,
+            label,
+        })
+    }
+
+    /// Nobody is listening, don't let the samples or rumble events pile up
+    fn drain(&mut self) {
+        self.cpu.mmu.apu.cur_sample.write().unwrap().clear();
+        self.cpu.mmu.cartridge.mbc.rumble_events();
+    }
+
+    fn finish(&self) -> Result<(), std::process::ExitCode> {
+        let label = self.label;
+        if let Some(opcode) = self.cpu.locked {
+            // not an emulator failure, real hardware hangs the same way
+            eprintln!("{label}CPU locked up on illegal opcode 0x{opcode:02x}");
+            eprintln!("{label}{}", self.cpu.state());
+        }
+        let save = self.cpu.mmu.cartridge.mbc.save();
+        if let (Some(path), Some(save)) = (
+            &self.save_path,
+            save.filter(|save| Some(save) != self.loaded.as_ref()),
+        ) {
+            if let Err(e) = std::fs::write(path, save) {
+                eprintln!("{label}couldn't write save {}: {e}", path.display());
+                return Err(std::process::ExitCode::from(EXIT_IO));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn window(
+    files: Option<Vec<gbrs::PickedFile>>,
+    ram_init: gbrs::core::mmu::RamInit,
+) -> eframe::Result {
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([400.0, 300.0])
@@ -20,7 +386,14 @@ fn main() -> eframe::Result {
     eframe::run_native(
         "Ferrous GB",
         native_options,
-        Box::new(|cc| Ok(Box::new(gbrs::GbApp::new(cc)))),
+        Box::new(move |cc| {
+            let mut app = match files {
+                Some(files) => gbrs::GbApp::with_rom(cc, files),
+                None => gbrs::GbApp::new(cc),
+            };
+            app.ram_init = ram_init;
+            Ok(Box::new(app))
+        }),
     )
 }
 
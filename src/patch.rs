@@ -0,0 +1,469 @@
+use anyhow::anyhow;
+
+/// Largest ROM a patch can produce, 512 banks
+const MAX_ROM_SIZE: usize = 512 * 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchFormat {
+    /// Works out the format from the magic bytes, None if it isn't a patch
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        match patch {
+            [b'P', b'A', b'T', b'C', b'H', ..] => Some(PatchFormat::Ips),
+            [b'U', b'P', b'S', b'1', ..] => Some(PatchFormat::Ups),
+            [b'B', b'P', b'S', b'1', ..] => Some(PatchFormat::Bps),
+            _ => None,
+        }
+    }
+}
+
+/// Applies an IPS, UPS or BPS patch to a ROM, returning the patched ROM
+pub fn apply(rom: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => ips(rom, patch),
+        Some(PatchFormat::Ups) => ups(rom, patch),
+        Some(PatchFormat::Bps) => bps(rom, patch),
+        None => Err(anyhow!("patch: not an IPS, UPS or BPS patch")),
+    }
+}
+
+/// Reads bytes from a patch, erroring rather than panicking on truncated patches
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(anyhow!(
+                "patch: unexpected end of patch at 0x{:x}",
+                self.pos
+            ))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn be(&mut self, len: usize) -> anyhow::Result<usize> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |acc, x| (acc << 8) | *x as usize))
+    }
+
+    /// UPS and BPS variable length number, 7 bits a byte with the top bit marking the end
+    fn varint(&mut self) -> anyhow::Result<usize> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let x = self.byte()?;
+            value = (x as usize & 0x7f)
+                .checked_mul(shift)
+                .and_then(|x| value.checked_add(x))
+                .ok_or(anyhow!("patch: number too large at 0x{:x}", self.pos))?;
+            if x & 0x80 > 0 {
+                return Ok(value);
+            }
+            shift = shift
+                .checked_shl(7)
+                .filter(|x| *x <= 1 << 56)
+                .ok_or(anyhow!("patch: number too large at 0x{:x}", self.pos))?;
+            value += shift;
+        }
+    }
+}
+
+fn ips(rom: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut out = rom.to_vec();
+    let mut r = Reader::new(patch, 5);
+    loop {
+        if r.data.get(r.pos..r.pos + 3) == Some(b"EOF") {
+            r.pos += 3;
+            break;
+        }
+        let offset = r.be(3)?;
+        let size = r.be(2)?;
+        // a zero size record is run length encoded
+        let (len, data) = if size == 0 {
+            let len = r.be(2)?;
+            (len, vec![r.byte()?; len])
+        } else {
+            (size, r.bytes(size)?.to_vec())
+        };
+        if offset + len > MAX_ROM_SIZE {
+            return Err(anyhow!(
+                "patch: writes past 0x{MAX_ROM_SIZE:x}, the largest ROM size"
+            ));
+        }
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        out[offset..offset + len].copy_from_slice(&data);
+    }
+    // some patchers add a truncation length after EOF
+    if let Ok(len) = r.be(3) {
+        out.truncate(len);
+    }
+    Ok(out)
+}
+
+/// Splits off and checks the CRC32 footer shared by UPS and BPS
+fn footer(rom: &[u8], patch: &[u8]) -> anyhow::Result<(u32, u32)> {
+    if patch.len() < 16 {
+        return Err(anyhow!("patch: too short"));
+    }
+    let crc = |at: usize| u32::from_le_bytes(patch[at..at + 4].try_into().unwrap());
+    let end = patch.len() - 12;
+    let (source, target, own) = (crc(end), crc(end + 4), crc(end + 8));
+    if crc32fast::hash(&patch[..end + 8]) != own {
+        return Err(anyhow!(
+            "patch: patch file is corrupt, its checksum doesn't match"
+        ));
+    }
+    if crc32fast::hash(rom) != source {
+        return Err(anyhow!(
+            "patch: made for a different ROM, expected CRC32 {source:08x} but this ROM is {:08x}",
+            crc32fast::hash(rom)
+        ));
+    }
+    Ok((source, target))
+}
+
+fn check_target(out: &[u8], target: u32) -> anyhow::Result<()> {
+    let crc = crc32fast::hash(out);
+    if crc != target {
+        return Err(anyhow!(
+            "patch: patched ROM should have CRC32 {target:08x} but has {crc:08x}"
+        ));
+    }
+    Ok(())
+}
+
+fn ups(rom: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (_, target) = footer(rom, patch)?;
+    let end = patch.len() - 12;
+    let mut r = Reader::new(&patch[..end], 4);
+    let input_size = r.varint()?;
+    let output_size = r.varint()?;
+    if input_size != rom.len() {
+        return Err(anyhow!(
+            "patch: made for a {input_size} byte ROM but this ROM is {} bytes",
+            rom.len()
+        ));
+    }
+
+    if output_size > MAX_ROM_SIZE {
+        return Err(anyhow!("patch: patched ROM would be {output_size} bytes"));
+    }
+
+    let mut out = rom.to_vec();
+    out.resize(output_size, 0);
+    let mut pos: usize = 0;
+    let bad = || anyhow!("patch: writes past the end of the patched ROM");
+    while r.pos < end {
+        pos = pos.checked_add(r.varint()?).ok_or_else(bad)?;
+        // XOR with the ROM until a zero byte, which is written too
+        loop {
+            let x = r.byte()?;
+            if let Some(b) = out.get_mut(pos) {
+                *b ^= x;
+            }
+            pos = pos.checked_add(1).ok_or_else(bad)?;
+            if x == 0 {
+                break;
+            }
+        }
+    }
+    check_target(&out, target)?;
+    Ok(out)
+}
+
+fn bps(rom: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (_, target) = footer(rom, patch)?;
+    let end = patch.len() - 12;
+    let mut r = Reader::new(&patch[..end], 4);
+    let source_size = r.varint()?;
+    let target_size = r.varint()?;
+    let metadata_size = r.varint()?;
+    r.bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(anyhow!(
+            "patch: made for a {source_size} byte ROM but this ROM is {} bytes",
+            rom.len()
+        ));
+    }
+
+    if target_size > MAX_ROM_SIZE {
+        return Err(anyhow!("patch: patched ROM would be {target_size} bytes"));
+    }
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    let bad = || anyhow!("patch: copies from outside the ROM");
+    while r.pos < end {
+        let data = r.varint()?;
+        let len = (data >> 2) + 1;
+        if len > target_size - out.len() {
+            return Err(anyhow!("patch: writes past the end of the patched ROM"));
+        }
+        match data & 0b11 {
+            // copy from the ROM at the same position
+            0 => {
+                let start = out.len();
+                out.extend_from_slice(rom.get(start..start + len).ok_or_else(bad)?);
+            }
+            // copy from the patch
+            1 => out.extend_from_slice(r.bytes(len)?),
+            // copy from elsewhere in the ROM
+            2 => {
+                source_offset = relative(source_offset, r.varint()?).ok_or_else(bad)?;
+                let bytes = rom
+                    .get(source_offset..source_offset.checked_add(len).ok_or_else(bad)?)
+                    .ok_or_else(bad)?;
+                out.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            // copy from what's been written so far, which can overlap what we're writing
+            _ => {
+                target_offset = relative(target_offset, r.varint()?).ok_or_else(bad)?;
+                for _ in 0..len {
+                    let b = *out.get(target_offset).ok_or_else(bad)?;
+                    out.push(b);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    check_target(&out, target)?;
+    Ok(out)
+}
+
+/// BPS offsets are signed, the sign is in the bottom bit
+fn relative(offset: usize, data: usize) -> Option<usize> {
+    if data & 1 > 0 {
+        offset.checked_sub(data >> 1)
+    } else {
+        offset.checked_add(data >> 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// UPS and BPS number encoding, the inverse of `Reader::varint`
+    fn varint(mut x: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (x & 0x7f) as u8;
+            x >>= 7;
+            if x == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            x -= 1;
+        }
+    }
+
+    /// Adds the UPS/BPS footer: source, target and patch CRC32s
+    fn seal(mut patch: Vec<u8>, rom: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32fast::hash(rom).to_le_bytes());
+        patch.extend(crc32fast::hash(target).to_le_bytes());
+        let own = crc32fast::hash(&patch);
+        patch.extend(own.to_le_bytes());
+        patch
+    }
+
+    fn rom() -> Vec<u8> {
+        (0..16).collect()
+    }
+
+    fn error(result: anyhow::Result<Vec<u8>>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn ips() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x00, 0x04, 0x00, 0x02, 0xaa, 0xbb]);
+        // run length encoded, past the end of the ROM so it grows
+        patch.extend([0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x03, 0xcc]);
+        patch.extend(b"EOF");
+        let mut target = rom();
+        target[4..6].copy_from_slice(&[0xaa, 0xbb]);
+        target[15] = 0xcc;
+        target.extend([0xcc, 0xcc]);
+        assert_eq!(apply(&rom(), &patch).unwrap(), target);
+
+        // truncation length after EOF
+        patch.extend([0x00, 0x00, 0x08]);
+        assert_eq!(apply(&rom(), &patch).unwrap(), target[..8]);
+    }
+
+    #[test]
+    fn ips_truncated() {
+        let patch = b"PATCH\x00\x00\x04\x00\x02\xaa".to_vec();
+        assert!(error(apply(&rom(), &patch)).contains("unexpected end of patch"));
+    }
+
+    #[test]
+    fn ips_past_largest_rom() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0xff, 0xff, 0xff, 0x00, 0x01, 0xaa]);
+        patch.extend(b"EOF");
+        assert!(error(apply(&rom(), &patch)).contains("largest ROM size"));
+    }
+
+    fn ups_patch() -> (Vec<u8>, Vec<u8>) {
+        let mut target = rom();
+        target[4] ^= 0x12;
+        target.extend([0, 0, 0, 0x34]);
+        let mut patch = b"UPS1".to_vec();
+        varint(16, &mut patch);
+        varint(20, &mut patch);
+        varint(4, &mut patch);
+        patch.extend([0x12, 0x00]);
+        // the zero ending the last run moved us on past byte 5
+        varint(13, &mut patch);
+        patch.extend([0x34, 0x00]);
+        (seal(patch, &rom(), &target), target)
+    }
+
+    #[test]
+    fn ups() {
+        let (patch, target) = ups_patch();
+        assert_eq!(apply(&rom(), &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn ups_truncated() {
+        // the CRCs are fine but the output size is missing
+        let mut patch = b"UPS1".to_vec();
+        varint(16, &mut patch);
+        let patch = seal(patch, &rom(), &rom());
+        assert!(error(apply(&rom(), &patch)).contains("unexpected end of patch"));
+        assert!(error(apply(&rom(), b"UPS1\x90\x90")).contains("too short"));
+    }
+
+    #[test]
+    fn ups_crc_mismatch() {
+        let (mut patch, _) = ups_patch();
+        patch[7] ^= 0xff;
+        assert!(error(apply(&rom(), &patch)).contains("checksum doesn't match"));
+
+        let (patch, _) = ups_patch();
+        let mut other = rom();
+        other[0] = 0xff;
+        assert!(error(apply(&other, &patch)).contains("made for a different ROM"));
+
+        // a patch that's intact but makes something other than it says it does
+        let (patch, _) = ups_patch();
+        let mut body = patch[..patch.len() - 12].to_vec();
+        let last = body.len() - 2;
+        body[last] = 0x35;
+        let (_, target) = ups_patch();
+        let patch = seal(body, &rom(), &target);
+        assert!(error(apply(&rom(), &patch)).contains("patched ROM should have CRC32"));
+    }
+
+    #[test]
+    fn ups_offset_overflow() {
+        let mut patch = b"UPS1".to_vec();
+        varint(16, &mut patch);
+        varint(16, &mut patch);
+        for _ in 0..5 {
+            varint(usize::MAX >> 2, &mut patch);
+            patch.extend([0x01, 0x00]);
+        }
+        let patch = seal(patch, &rom(), &rom());
+        assert!(error(apply(&rom(), &patch)).contains("writes past the end"));
+    }
+
+    /// A BPS action, the kind is in the bottom 2 bits and lengths start at 1
+    fn action(len: usize, kind: usize, out: &mut Vec<u8>) {
+        varint(((len - 1) << 2) | kind, out);
+    }
+
+    fn bps_patch(actions: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = b"BPS1".to_vec();
+        varint(16, &mut patch);
+        varint(target.len(), &mut patch);
+        varint(0, &mut patch);
+        patch.extend(actions);
+        seal(patch, &rom(), target)
+    }
+
+    #[test]
+    fn bps() {
+        let target = [0, 1, 2, 3, 0xaa, 0xbb, 10, 11, 12, 0xaa, 0xbb, 10, 11];
+        let mut actions = Vec::new();
+        // 4 bytes from the ROM where they are
+        action(4, 0, &mut actions);
+        // 2 bytes from the patch
+        action(2, 1, &mut actions);
+        actions.extend([0xaa, 0xbb]);
+        // 3 bytes from ROM offset 10
+        action(3, 2, &mut actions);
+        varint(10 << 1, &mut actions);
+        // 4 bytes of what's been written from offset 4
+        action(4, 3, &mut actions);
+        varint(4 << 1, &mut actions);
+        let patch = bps_patch(&actions, &target);
+        assert_eq!(apply(&rom(), &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_truncated() {
+        let target = [0xaa, 0xbb];
+        let mut actions = Vec::new();
+        action(2, 1, &mut actions);
+        actions.push(0xaa);
+        let patch = bps_patch(&actions, &target);
+        assert!(error(apply(&rom(), &patch)).contains("unexpected end of patch"));
+    }
+
+    #[test]
+    fn bps_out_of_range() {
+        let target = [0; 4];
+        let mut actions = Vec::new();
+        action(4, 2, &mut actions);
+        varint(100 << 1, &mut actions);
+        let patch = bps_patch(&actions, &target);
+        assert!(error(apply(&rom(), &patch)).contains("copies from outside the ROM"));
+
+        // an offset so large adding the length to it overflows
+        let mut actions = Vec::new();
+        action(4, 2, &mut actions);
+        varint((usize::MAX >> 4) << 1, &mut actions);
+        let patch = bps_patch(&actions, &target);
+        assert!(error(apply(&rom(), &patch)).contains("copies from outside the ROM"));
+
+        // and one that would write more than the target size
+        let mut actions = Vec::new();
+        action(8, 0, &mut actions);
+        let patch = bps_patch(&actions, &target);
+        assert!(error(apply(&rom(), &patch)).contains("writes past the end"));
+    }
+
+    #[test]
+    fn not_a_patch() {
+        assert_eq!(PatchFormat::detect(b"PK\x03\x04"), None);
+        assert!(error(apply(&rom(), b"hello")).contains("not an IPS, UPS or BPS patch"));
+    }
+}
@@ -9,7 +9,7 @@ use cpal::{
 };
 use egui::{Color32, Key, TextureHandle, Vec2};
 
-use crate::core::{Buttons, cpu::Cpu};
+use crate::core::{Buttons, cpu::Cpu, mbc::RumbleEvent};
 
 #[derive(Default)]
 pub struct Debugger {
@@ -26,6 +26,14 @@ pub struct Screen {
     pub last_frame: u128,
     pub handle: Option<Handle>,
     pub debugger: Debugger,
+    pub rumble: bool,
+    /// Set once emulation fails, along with the CPU state at the time
+    pub crash: Option<Crash>,
+}
+
+pub struct Crash {
+    pub error: String,
+    pub state: String,
 }
 
 impl Screen {
@@ -48,22 +56,15 @@ impl Screen {
             last_frame: 0,
             handle,
             debugger: Debugger::default(),
+            rumble: false,
+            crash: None,
         }
     }
 
     pub fn frame(&mut self) -> anyhow::Result<Vec<Color32>> {
         // sync the cpu to the audio
         if self.cpu.mmu.apu.cur_sample.read().unwrap().len() <= MAX_AUDIO_BUFFER {
-            for _ in 0..70224 {
-                // is this right?
-                self.cpu.cycle()?;
-                self.cpu.mmu.apu.clock(self.cpu.mmu.sys);
-                self.cpu.ppu.clock(&mut self.cpu.mmu)?;
-
-                self.cpu.mmu.sys = self.cpu.mmu.sys.wrapping_add(1);
-                // TODO: add a way to look at falling edges on sys/div
-                // off the top of my head, APU needs it, timer needs it...
-            }
+            self.cpu.frame()?;
         }
 
         let f = self
@@ -99,6 +100,10 @@ impl Screen {
     }
 
     pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if let Some(crash) = &self.crash {
+            crash_ui(ui, crash);
+            return;
+        }
         ui.ctx().request_repaint();
         let buttons = ui.input(|i| Buttons {
             up: i.key_down(Key::ArrowUp),
@@ -110,14 +115,17 @@ impl Screen {
             a: i.key_down(Key::A),
             b: i.key_down(Key::B),
         });
-        self.cpu.mmu.buttons = buttons;
-        // TODO: Joypad interrupt
+        self.cpu.mmu.set_buttons(buttons);
 
         let frame = match self.frame() {
             Ok(x) => x,
             Err(e) => {
-                log::info!("screen: crashed after {} cycles", self.cpu.cycles);
-                panic!("error: {e}");
+                log::error!("screen: crashed after {} cycles: {e:#}", self.cpu.cycles);
+                self.crash = Some(Crash {
+                    error: format!("{e:#}"),
+                    state: self.cpu.state(),
+                });
+                return;
             }
         };
         self.screen_texture.set(
@@ -128,18 +136,63 @@ impl Screen {
             },
             egui::TextureOptions::NEAREST,
         );
+        for event in self.cpu.mmu.cartridge.mbc.rumble_events() {
+            self.rumble = event == RumbleEvent::On;
+        }
+
         let sized = egui::load::SizedTexture::from_handle(&self.screen_texture);
         let max_size = 2.0 * Vec2::new(160.0, 144.0);
         let min_size = ui.available_size();
         let target_size = min_size.min(max_size);
-        ui.add(egui::Image::new(sized).fit_to_exact_size(target_size));
+        let (rect, response) = ui.allocate_exact_size(target_size, egui::Sense::drag());
+
+        // holding the mouse down on the screen tilts towards the pointer, otherwise use IJKL
+        let tilt = match response.interact_pointer_pos() {
+            Some(pos) if response.is_pointer_button_down_on() => {
+                (pos - rect.center()) / (rect.size() / 2.0)
+            }
+            _ => ui.input(|i| {
+                Vec2::new(
+                    (i.key_down(Key::L) as i8 - i.key_down(Key::J) as i8) as f32,
+                    (i.key_down(Key::K) as i8 - i.key_down(Key::I) as i8) as f32,
+                )
+            }),
+        };
+        self.cpu.mmu.cartridge.mbc.set_tilt(tilt.x, tilt.y);
+
+        let shake = if self.rumble {
+            // jitter the screen back and forth while the motor is running
+            if ui.ctx().cumulative_pass_nr() % 2 == 0 {
+                Vec2::new(2.0, 0.0)
+            } else {
+                Vec2::new(-2.0, 0.0)
+            }
+        } else {
+            Vec2::ZERO
+        };
+        egui::Image::new(sized).paint_at(ui, rect.translate(shake));
+        if let Some(opcode) = self.cpu.locked {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!("CPU locked up on illegal opcode 0x{opcode:02x}"),
+            );
+        }
+        if let Some(tone) = self.cpu.mmu.cartridge.mbc.tone() {
+            ui.label(format!("cartridge speaker: tone {tone:x?}"));
+        }
         ui.checkbox(&mut self.cpu.logging, "logging enabled");
         ui.label(format!("frame time: {}ms", self.last_frame));
         ui.label(format!("lcdc: 0b{:08b}", self.cpu.mmu.io.lcdc));
 
         if self.debugger.show_vram {
             egui::Window::new("VRAM").show(ui.ctx(), |ui| {
-                let debug_frame = self.vram_debug_frame().unwrap();
+                let debug_frame = match self.vram_debug_frame() {
+                    Ok(x) => x,
+                    Err(e) => {
+                        ui.label(format!("couldn't read VRAM: {e}"));
+                        return;
+                    }
+                };
                 self.vram_texture.set(
                     egui::ColorImage {
                         size: [128, 64 * 4],
@@ -155,6 +208,18 @@ impl Screen {
     }
 }
 
+fn crash_ui(ui: &mut egui::Ui, crash: &Crash) {
+    egui::Frame::group(ui.style())
+        .stroke(egui::Stroke::new(1.0, ui.visuals().error_fg_color))
+        .show(ui, |ui| {
+            ui.colored_label(ui.visuals().error_fg_color, "The emulator has stopped");
+            ui.label(&crash.error);
+            ui.separator();
+            ui.label("CPU state:");
+            ui.monospace(&crash.state);
+        });
+}
+
 pub struct Handle(Stream);
 
 pub fn beep(data: ApuSamples) -> Handle {